@@ -0,0 +1,39 @@
+//! Exposes build metadata to the crate as compile-time environment variables.
+use std::path::Path;
+use std::process::Command;
+
+use chrono::Utc;
+
+/// Runs a command, returning its trimmed stdout or "unknown" on failure.
+fn output_of(cmd: &str, args: &[&str]) -> String {
+    Command::new(cmd)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|s| s.trim().to_owned())
+        .unwrap_or_else(|| "unknown".to_owned())
+}
+
+fn main() {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+
+    println!("cargo:rustc-env=GIT_SHA={}", output_of("git", &["rev-parse", "HEAD"]));
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", Utc::now().to_rfc3339());
+    println!("cargo:rustc-env=RUSTC_VERSION={}", output_of(&rustc, &["--version"]));
+
+    // A commit moves the branch HEAD points at, not HEAD itself; the ref may
+    // also live in packed-refs once git packs it.
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    let mut watched = vec![".git/packed-refs".to_owned()];
+    // A detached HEAD has no branch ref; HEAD itself moves on a commit.
+    let head_ref = output_of("git", &["symbolic-ref", "-q", "HEAD"]);
+    if head_ref != "unknown" {
+        watched.push(format!(".git/{}", head_ref));
+    }
+    // Watching a missing file would rerun the build script on every build.
+    for path in watched.iter().filter(|path| Path::new(path).exists()) {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+}
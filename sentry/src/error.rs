@@ -35,6 +35,14 @@ pub enum ErrorKind {
 
 	#[fail(display = "Invalid Vector length: got {}, expected {}", got, expected)]
 	VecLength { got: usize, expected: usize },
+
+	/// A key shorter than the minimum required size.
+	#[fail(display = "Key of {} bits is too short, expected at least {} bits", bits, min_bits)]
+	WeakKey { bits: usize, min_bits: usize },
+
+	/// A token whose claims are missing or malformed.
+	#[fail(display = "Malformed token: {}", _0)]
+	Malformed(String),
 }
 
 /// Sentry application error.
@@ -140,7 +148,7 @@ impl From<argonautica::Error> for Error {
 	}
 }
 
-/// ParseError handles the parse validation errors for HashVersion V1.
+/// ParseError handles the parse validation errors of stored password hashes.
 #[derive(Debug)]
 pub enum ParseError {
 	/// base64 decode error
@@ -151,7 +159,7 @@ pub enum ParseError {
 	InvalidVecLen,
 	/// slice validation
 	InvalidSlice,
-	/// byte size validation can occur in  `salt` or  `hash` types of V1Hash
+	/// byte size validation of a hash component
 	InvalidLen,
 }
 
@@ -1,4 +1,5 @@
 use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 use argonautica::{
 	self,
@@ -8,133 +9,137 @@ use argonautica::{
 };
 use crate::error::{Error, ErrorKind, ParseError};
 use failure::format_err;
+use hmac::{Hmac, Mac};
+use once_cell::sync::OnceCell;
+use sha2::Sha256;
 
 const SALT_SIZE : usize = 32;
 
+/// Argon2 cost parameters of new hashes, loaded once by `init_hash_params`.
+static HASH_PARAMS: OnceCell<HashParams> = OnceCell::new();
+
 enum HashVersion {
+	/// Legacy `1$<salt>$<hash>` layout, which kept only part of the Argon2
+	/// output and can't be verified; its passwords must be reset.
 	V1,
+	/// `2$<encoded Argon2 hash>`, see `V2Hash`.
+	V2,
 }
 
-/// Hash Version v1
+/// Hash Version v1, v2
 impl HashVersion {
 	pub fn from_hash(hash: &str) -> Option<HashVersion> {
 		let version: String = hash.chars().take_while(|c| c != &'$').collect();
 		match version.as_ref() {
 			"1" => Some(HashVersion::V1),
+			"2" => Some(HashVersion::V2),
 			_   => None,
 		}
 	}
 }
 
-/// Hashing algorithm v1, using Argon2id variant with 256-bit salt.
-struct V1Hash {
-	pub salt: [u8; SALT_SIZE],
-	pub hash: [u8; 32],
+/// Hashing algorithm v2, using the configured Argon2 variant (Argon2id by
+/// default) with 256-bit salt.  Stored as `2$` followed by the encoded Argon2
+/// hash, which records the variant, cost parameters and salt it was made
+/// with, so it keeps verifying when the configuration changes.
+struct V2Hash {
+	pub encoded: String,
 }
 
-impl V1Hash {
+impl V2Hash {
 
-	/// String hash method.
-	#[allow(dead_code)]
-	pub fn hash_password(password: &str) -> Result<Self, Error> {
+	/// Hashes a password, peppered with the base64 encoded `pepper` if any.
+	pub fn hash_password(password: &str, pepper: Option<&str>) -> Result<Self, Error> {
 		use crate::random;
 
 		let salt = random::generate_random_byte_array();
 		let slen = salt.len();
 
 		// validate salt size
-		if !(slen == 32) {
+		if !(slen == SALT_SIZE) {
 			return Err(
 				ErrorKind::Msg(format!(
-						"Expected salt size (32), got salt size({})",
-						slen)).into())
+						"Expected salt size ({}), got salt size({})",
+						SALT_SIZE, slen)).into())
 		}
 
-		let encrypt = argon2id(password, &salt)?;
-		let mut hash = [0u8; 32];
-		for (&x, p) in encrypt.into_bytes()
-			.iter().zip(hash.iter_mut()){
-				*p = x;
-			}
-
-		Ok (V1Hash { salt, hash })
-	}
-
-	/// Checks password with Argon2.
-	pub fn check(&self, password: &str) -> Result<bool, Error> {
-		let key = load_env_var("SECRET_KEY")?;
-		let mut verifier = Verifier::new();
-		let is_valid = if verifier
-			.with_secret_key(&key)
-			.with_password(password)
-			.verify()
-			.is_ok()
-			{
-				true
-			} else {
-				false
-			};
+		let password = match pepper {
+			Some(key) => peppered(password, key)?,
+			None => password.to_owned(),
+		};
+		let encoded = argon2(&password, &salt, hash_variant()?)?;
+
+		Ok (V2Hash { encoded })
+	}
 
-		Ok(is_valid)
+	/// Checks password with Argon2.  With a pepper, the peppered password is
+	/// tried first, then the plain one for hashes created before the pepper
+	/// was introduced, which then need a rehash.  A matching hash made with
+	/// another variant than the configured one needs a rehash too.
+	pub fn check(&self, password: &str, pepper: Option<&str>) -> Result<PasswordCheck, Error> {
+		let check = match pepper {
+			Some(key) => {
+				if self.verify(&peppered(password, key)?)? {
+					PasswordCheck::Valid
+				} else if self.verify(password)? {
+					PasswordCheck::NeedsRehash
+				} else {
+					PasswordCheck::Invalid
+				}
+			},
+			None => {
+				if self.verify(password)? {
+					PasswordCheck::Valid
+				} else {
+					PasswordCheck::Invalid
+				}
+			},
+		};
+
+		if check == PasswordCheck::Valid && self.variant() != Some(hash_variant()?) {
+			return Ok(PasswordCheck::NeedsRehash);
+		}
+		Ok(check)
+	}
 
+	/// The Argon2 variant recorded in the encoded hash.
+	fn variant(&self) -> Option<Variant> {
+		variant_of(&self.encoded)
 	}
 
-	/// takes in &[u8] and validates len size on `salt`, `hash` types of V1Hash.
-	pub fn from_bytes<B: AsRef<[u8]>, C: AsRef<[u8]>>(b0: B, b1: C) ->
-		Result<Self, ParseError> {
-			let salt_byte = b0.as_ref();
-			let hash_byte= b1.as_ref();
-			validate!(
-				salt_byte.len() != 32 || hash_byte.len() != 32,
-				ParseError::InvalidLen
-			);
-			let mut salt = [0u8; 32];
-			let mut hash = [0u8; 32];
-			salt.copy_from_slice(salt_byte);
-			hash.copy_from_slice(hash_byte);
-			Ok(V1Hash { salt, hash } )
+	/// Verifies a single password candidate against the encoded hash, with
+	/// the variant it records.
+	fn verify(&self, password: &str) -> Result<bool, Error> {
+		if self.variant().is_none() {
+			return Err(ErrorKind::Message("Hash doesn't record an Argon2 variant").into());
 		}
+		verify_encoded(password, &self.encoded)
+	}
 }
 
-impl FromStr for V1Hash {
+impl FromStr for V2Hash {
 	type Err = ParseError;
 
 	fn from_str(s: &str) -> Result<Self, ParseError> {
-		let parts: Vec<_> = s.split('$').collect();
-		validate!(parts.len() != 3, ParseError::InvalidVecLen);
-		validate!(parts[0] != "1", ParseError::InvalidSlice);
-
-		let vec_salt = base64::decode(parts[1])
-			.map_err(|e| ParseError::DecodeError(e))?;
-
-		let vec_hash = base64::decode(parts[2])
-			.map_err(|e| ParseError::DecodeError(e))?;
+		let mut parts = s.splitn(2, '$');
+		validate!(parts.next() == Some("2"), ParseError::InvalidSlice);
 
-		let v1hash_from_bytes = V1Hash::from_bytes(&vec_salt, &vec_hash)?;
+		let encoded = parts.next().ok_or(ParseError::InvalidVecLen)?;
+		validate!(variant_of(encoded).is_some(), ParseError::InvalidSlice);
 
-		let salt = v1hash_from_bytes.salt;
-		let hash = v1hash_from_bytes.hash;
-
-		Ok(Self { salt, hash })
+		Ok(Self { encoded: encoded.to_owned() })
 	}
 }
 
-/// ToString implementation for V1Hash
-impl ToString for V1Hash {
+/// ToString implementation for V2Hash
+impl ToString for V2Hash {
 	fn to_string(&self) -> String {
-		let as_str = format!(
-			"1${}${}",
-			// base64::encode(&self.salt).as_ref(),
-			base64::encode(&self.salt),
-			// base64::encode(&self.hash).as_ref(),
-			base64::encode(&self.hash),
-			);
-		as_str
+		format!("2${}", self.encoded)
 	}
 }
 
-/// Into() String implementation for V1Hash
-impl Into<String> for V1Hash {
+/// Into() String implementation for V2Hash
+impl Into<String> for V2Hash {
 	fn into(self) -> String {
 		self.to_string()
 	}
@@ -142,12 +147,25 @@ impl Into<String> for V1Hash {
 
 /// argon2id algorithm hashes a password for login sessions
 pub fn argon2id(pwd: &str, salt: &[u8]) -> Result<String, Error> {
+	argon2(pwd, salt, Variant::Argon2id)
+}
+
+/// Hashes a password with the given Argon2 variant, returning the encoded
+/// hash, e.g. `$argon2id$v=19$m=4096,t=192,p=8$<salt>$<hash>`.
+pub fn argon2(pwd: &str, salt: &[u8], variant: Variant) -> Result<String, Error> {
+	argon2_with(pwd, salt, variant, hash_params()?)
+}
+
+fn argon2_with(pwd: &str, salt: &[u8], variant: Variant, params: &HashParams)
+	-> Result<String, Error> {
 	let key = load_env_var("SECRET_KEY")?;
 
-	let mut a2id = Hasher::default();
-	let raw_hash = a2id
+	let mut hasher = Hasher::default();
+	let raw_hash = hasher
 		.configure_hash_len(32)
-		.configure_variant(Variant::Argon2id)
+		.configure_iterations(params.iterations)
+		.configure_memory_size(params.memory_kib)
+		.configure_variant(variant)
 		.configure_version(Version::_0x13)
 		.with_password(pwd)
 		.with_salt(salt)
@@ -157,21 +175,212 @@ pub fn argon2id(pwd: &str, salt: &[u8]) -> Result<String, Error> {
 	Ok(hash_encoded)
 }
 
-/// Check a password against a hash which has been generated by this crate.
-pub fn check_password(pwd: &str, hash: &str) -> bool {
+/// Verifies a password against an encoded Argon2 hash.  The variant and
+/// parameters are read from the encoded hash, so hashes made with another
+/// variant than the configured one still verify.
+pub fn verify_encoded(pwd: &str, encoded: &str) -> Result<bool, Error> {
+	let key = load_env_var("SECRET_KEY")?;
+	let mut verifier = Verifier::new();
+	let is_valid = verifier
+		.with_hash(encoded)
+		.with_secret_key(&key)
+		.with_password(pwd)
+		.verify()?;
+
+	Ok(is_valid)
+}
+
+/// The Argon2 variant of an encoded hash, if it names one.
+pub fn variant_of(encoded: &str) -> Option<Variant> {
+	encoded.split('$').nth(1).and_then(|variant| variant.parse().ok())
+}
+
+/// The Argon2 variant new passwords are hashed with, read from the
+/// PASSWORD_HASH_VARIANT environment variable (`argon2id` or `argon2i`).
+/// Defaults to Argon2id, the recommended variant.
+pub fn hash_variant() -> Result<Variant, Error> {
+	match std::env::var("PASSWORD_HASH_VARIANT") {
+		Ok(variant) => match variant.as_ref() {
+			"argon2id" => Ok(Variant::Argon2id),
+			"argon2i" => Ok(Variant::Argon2i),
+			_ => Err(ErrorKind::Msg(format!(
+						"Invalid PASSWORD_HASH_VARIANT {}, expected argon2id or argon2i",
+						variant)).into()),
+		},
+		Err(_) => Ok(Variant::Argon2id),
+	}
+}
+
+/// Argon2 cost parameters of new hashes.  Encoded hashes record theirs, so
+/// changing them doesn't break existing hashes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HashParams {
+	/// Number of passes over the memory (`t`).
+	pub iterations: u32,
+	/// Memory used, in KiB (`m`).
+	pub memory_kib: u32,
+}
+
+impl Default for HashParams {
+	/// The argonautica defaults, `t=192` and `m=4096`.
+	fn default() -> Self {
+		HashParams { iterations: 192, memory_kib: 4096 }
+	}
+}
+
+/// Loads the Argon2 cost parameters, calibrating them if configured, so the
+/// first password hashed doesn't pay for it.  Call it once at startup.
+///
+/// `ARGON2_ITERATIONS` and `ARGON2_MEMORY_KIB` set them explicitly.  When
+/// neither is set and `ARGON2_TARGET_MS` is, the iterations are calibrated
+/// to hash a password in about that many milliseconds on this host.
+/// Otherwise the defaults are used.
+pub fn init_hash_params() -> Result<HashParams, Error> {
+	hash_params().map(|params| *params)
+}
+
+fn hash_params() -> Result<&'static HashParams, Error> {
+	HASH_PARAMS.get_or_try_init(|| {
+		let defaults = HashParams::default();
+		let iterations = env_u32("ARGON2_ITERATIONS")?;
+		let memory_kib = env_u32("ARGON2_MEMORY_KIB")?;
+
+		let params = match (iterations, memory_kib, env_u32("ARGON2_TARGET_MS")?) {
+			(None, None, Some(target_ms)) => {
+				calibrate(Duration::from_millis(u64::from(target_ms)), defaults.memory_kib)?
+			},
+			(iterations, memory_kib, _) => HashParams {
+				iterations: iterations.unwrap_or(defaults.iterations),
+				memory_kib: memory_kib.unwrap_or(defaults.memory_kib),
+			},
+		};
+
+		log::info!(
+			"Hashing passwords with argon2 t={} m={}",
+			params.iterations, params.memory_kib);
+		Ok(params)
+	})
+}
+
+/// Picks the iterations hashing a password in about `target` with
+/// `memory_kib` of memory, timing the hash `V2Hash::hash_password` runs.
+/// The cost of one pass is estimated from a short run, then checked.
+fn calibrate(target: Duration, memory_kib: u32) -> Result<HashParams, Error> {
+	use crate::random;
+
+	const PROBE_ITERATIONS: u32 = 8;
+
+	let password = random::password();
+	let salt = random::generate_random_byte_array();
+	let time = |params: &HashParams| -> Result<Duration, Error> {
+		let started = Instant::now();
+		argon2_with(&password, &salt, hash_variant()?, params)?;
+		Ok(started.elapsed())
+	};
+
+	let probe = time(&HashParams { iterations: PROBE_ITERATIONS, memory_kib })?;
+	let pass_nanos = (probe.as_nanos() / u128::from(PROBE_ITERATIONS)).max(1);
+	let iterations = (target.as_nanos() / pass_nanos).max(1).min(u128::from(u32::max_value()));
+	let params = HashParams { iterations: iterations as u32, memory_kib };
+
+	log::info!(
+		"Calibrated argon2 to t={} m={}, hashing in {:?} for a {:?} target",
+		params.iterations, params.memory_kib, time(&params)?, target);
+	Ok(params)
+}
+
+/// Parses an optional numeric environment variable.
+fn env_u32(name: &str) -> Result<Option<u32>, Error> {
+	match std::env::var(name) {
+		Ok(value) => value.parse::<u32>().map(Some).map_err(|_| {
+			ErrorKind::Msg(format!("Invalid {} {}, expected a number", name, value)).into()
+		}),
+		Err(_) => Ok(None),
+	}
+}
+
+/// The outcome of checking a password against a hash.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PasswordCheck {
+	/// The password matches.
+	Valid,
+	/// The password matches a hash created without the configured pepper,
+	/// or with another Argon2 variant than the configured one.  Rehash it
+	/// with `hash_password` while the plain password is at hand.
+	NeedsRehash,
+	/// The password doesn't match.
+	Invalid,
+}
+
+/// HMAC-SHA256s the password with the base64 encoded server-side pepper,
+/// base64 encoded.
+///
+/// Rotating the pepper: hashes made with the previous pepper stop verifying,
+/// so rotate by first removing the pepper (hashes of the old one are then
+/// unusable too) or by forcing a password reset.  Introducing a pepper is
+/// seamless, as un-peppered hashes still verify and are flagged for rehash.
+fn peppered(password: &str, pepper: &str) -> Result<String, Error> {
+	let pepper = base64::decode(pepper)
+		.map_err(|e| format_err!("Invalid PASSWORD_PEPPER. Cause: {}", e))?;
+
+	let mut mac = Hmac::<Sha256>::new_varkey(&pepper)
+		.map_err(|_| ErrorKind::Message("Invalid PASSWORD_PEPPER length"))?;
+	mac.input(password.as_bytes());
+
+	Ok(base64::encode(&mac.result().code()))
+}
+
+/// The base64 encoded pepper of the PASSWORD_PEPPER environment variable, if
+/// set.
+fn password_pepper() -> Option<String> {
+	std::env::var("PASSWORD_PEPPER").ok()
+}
+
+/// Hashes a password for storage, with the configured variant, cost
+/// parameters and pepper.  Also use it to rehash a password reported as
+/// `PasswordCheck::NeedsRehash`.
+pub fn hash_password(pwd: &str) -> Result<String, Error> {
+	let pepper = password_pepper();
+	Ok(V2Hash::hash_password(pwd, pepper.as_ref().map(String::as_str))?.into())
+}
+
+/// Verify a password against a hash which has been generated by this crate.
+/// A hash that can't be checked, e.g. with an invalid PASSWORD_PEPPER, is
+/// logged and reported as `Invalid`.
+pub fn verify_password(pwd: &str, hash: &str) -> PasswordCheck {
+	let pepper = password_pepper();
+	verify_password_with(pwd, hash, pepper.as_ref().map(String::as_str))
+}
+
+fn verify_password_with(pwd: &str, hash: &str, pepper: Option<&str>) -> PasswordCheck {
 	match HashVersion::from_hash(hash) {
 		Some(HashVersion::V1) => {
-			let v1_hash: V1Hash = match hash.parse() {
-				Ok(v1_hash) => v1_hash,
-				Err(_) => return false,
+			log::warn!("Unable to check a legacy v1 password hash; the password must be reset");
+			PasswordCheck::Invalid
+		}
+		Some(HashVersion::V2) => {
+			let v2_hash: V2Hash = match hash.parse() {
+				Ok(v2_hash) => v2_hash,
+				Err(e) => {
+					log::error!("Unable to parse password hash: {}", e);
+					return PasswordCheck::Invalid;
+				}
 			};
 
-			v1_hash.check(pwd).unwrap()
+			v2_hash.check(pwd, pepper).unwrap_or_else(|e| {
+				log::error!("Unable to check password: {}", e);
+				PasswordCheck::Invalid
+			})
 		}
-		None => false,
+		None => PasswordCheck::Invalid,
 	}
 }
 
+/// Check a password against a hash which has been generated by this crate.
+pub fn check_password(pwd: &str, hash: &str) -> bool {
+	verify_password(pwd, hash) != PasswordCheck::Invalid
+}
+
 /// gets environment variable
 ///
 /// TODO: Use thread-safe cell for static reference of jwt_key and secret_key.
@@ -209,9 +418,77 @@ mod test {
 		for _ in 0..10 {
 			let password: String = random::password();
 
-			let v1_hash: String = V1Hash::hash_password(&password).unwrap().into();
-			eprintln!("{:?}", v1_hash);
-			assert!(true, check_password(&password, &v1_hash))
+			let v2_hash: String = V2Hash::hash_password(&password, None).unwrap().into();
+			assert!(v2_hash.starts_with("2$$argon2"));
+			assert_eq!(verify_password_with(&password, &v2_hash, None), PasswordCheck::Valid);
+			assert_eq!(verify_password_with("not-the-password", &v2_hash, None),
+				PasswordCheck::Invalid);
+		}
+	}
+
+	const PEPPER: &str = "c2VydmVyLXNpZGUtcGVwcGVyLTI1Ni1iaXRzLWxvbmc=";
+
+	#[test]
+	pub fn peppered_hash_verifies() {
+		let hash: String = V2Hash::hash_password("WY5cpFbAY0ns", Some(PEPPER)).unwrap().into();
+
+		assert_eq!(verify_password_with("WY5cpFbAY0ns", &hash, Some(PEPPER)), PasswordCheck::Valid);
+		assert_eq!(verify_password_with("not-the-password", &hash, Some(PEPPER)),
+			PasswordCheck::Invalid);
+		// Without the pepper, a peppered hash doesn't verify.
+		assert_eq!(verify_password_with("WY5cpFbAY0ns", &hash, None), PasswordCheck::Invalid);
+	}
+
+	#[test]
+	pub fn unpeppered_hash_needs_rehash() {
+		let hash: String = V2Hash::hash_password("WY5cpFbAY0ns", None).unwrap().into();
+
+		assert_eq!(verify_password_with("WY5cpFbAY0ns", &hash, Some(PEPPER)),
+			PasswordCheck::NeedsRehash);
+		assert_eq!(verify_password_with("not-the-password", &hash, Some(PEPPER)),
+			PasswordCheck::Invalid);
+	}
+
+	#[test]
+	pub fn invalid_pepper_is_invalid() {
+		let hash: String = V2Hash::hash_password("WY5cpFbAY0ns", None).unwrap().into();
+
+		assert!(V2Hash::hash_password("WY5cpFbAY0ns", Some("not base64!")).is_err());
+		assert_eq!(verify_password_with("WY5cpFbAY0ns", &hash, Some("not base64!")),
+			PasswordCheck::Invalid);
+	}
+
+	#[test]
+	pub fn legacy_v1_hash_is_invalid() {
+		let salt = base64::encode(&[1u8; 32][..]);
+		let hash = base64::encode(&[2u8; 32][..]);
+
+		assert_eq!(verify_password_with("WY5cpFbAY0ns", &format!("1${}${}", salt, hash), None),
+			PasswordCheck::Invalid);
+	}
+
+	#[test]
+	pub fn variants_cross_verify() {
+		use crate::random;
+
+		let salt = random::generate_random_byte_array();
+		let argon2i = argon2("WY5cpFbAY0ns", &salt, Variant::Argon2i).unwrap();
+		let argon2id = argon2("WY5cpFbAY0ns", &salt, Variant::Argon2id).unwrap();
+
+		assert_eq!(variant_of(&argon2i), Some(Variant::Argon2i));
+		assert_eq!(variant_of(&argon2id), Some(Variant::Argon2id));
+
+		// Argon2id is the configured variant; an Argon2i hash still
+		// verifies, and is flagged for rehash.
+		let argon2i: String = V2Hash { encoded: argon2i }.into();
+		let argon2id: String = V2Hash { encoded: argon2id }.into();
+		assert_eq!(verify_password_with("WY5cpFbAY0ns", &argon2i, None), PasswordCheck::NeedsRehash);
+		assert_eq!(verify_password_with("WY5cpFbAY0ns", &argon2id, None), PasswordCheck::Valid);
+		for hash in &[argon2i, argon2id] {
+			assert_eq!(verify_password_with("not-the-password", hash, None), PasswordCheck::Invalid);
 		}
+
+		assert_eq!(verify_password_with("WY5cpFbAY0ns", "2$$argon2x$v=19$m=8,t=1,p=1$c2FsdA$aGFzaA", None),
+			PasswordCheck::Invalid);
 	}
 }
@@ -11,3 +11,15 @@ pub mod error;
 pub mod hash;
 pub mod random;
 pub mod token;
+
+/// Loads and validates everything the crate reads from its environment: the
+/// token issuer and private key, the `aud` obfuscator key and the Argon2
+/// cost parameters.  Call it once at startup, so a missing or invalid key
+/// fails the service before it accepts requests rather than on the first
+/// token or password.
+pub fn init() -> Result<(), error::Error> {
+	token::init_token_cfg()?;
+	token::init_thread_safe_key()?;
+	hash::init_hash_params()?;
+	Ok(())
+}
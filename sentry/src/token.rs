@@ -16,7 +16,7 @@ use log;
 use magic_crypt::MagicCrypt;
 use once_cell::sync::OnceCell;
 use serde::{Serialize, Deserialize};
-use crate::error::Error;
+use crate::error::{Error, ErrorKind};
 
 // Info: Use .der format for PEM key encryption
 //
@@ -49,15 +49,22 @@ use crate::error::Error;
 //
 // Reference: https://github.com/rustasync/surf
 
-/// Private key used to sign tokens.
+/// Development private key, used to sign tokens when TOKEN_PRIVATE_KEY_PATH
+/// is unset.  Release builds don't embed it and require the path.
+#[cfg(debug_assertions)]
 const PKEY: &[u8] = include_bytes!("../support/keys/sessions01/1569901546-private.der");
-/// Name of the issuer for the token generating service.
+/// Default name of the issuer for the token generating service, unless
+/// overridden by the TOKEN_ISSUER environment variable.
 const ISS: &'static str = "sessions";
 /// client data used for audience_identifier obfuscation.
 const AUD: &'static str = "email@example.com";
-/// Path of the public key.  It will be consumed by a keyserver.
+/// Default path of the public key, unless overridden by the TOKEN_KID
+/// environment variable.  It will be consumed by a keyserver.
 const KID: &'static str = "sessions01/1569901546-public.der";
 
+/// Name of the ExtraClaim holding the TokenType.
+const TOKEN_TYPE_CLAIM: &'static str = "TokenType";
+
 /// Token lifespans
 const REFRESH_LIFESPAN: i64 = 15 * 60;
 const NORMAL_LIFESPAN: i64 = 60 * 60;
@@ -65,23 +72,124 @@ const NORMAL_LIFESPAN: i64 = 60 * 60;
 /// Master key will be consumed by the `aud` magic_crypt encrypt method
 static MASTER_ASAP_KEY: OnceCell<String> = OnceCell::new();
 
+/// Token generator settings, loaded once by `init_token_cfg`.
+static TOKEN_CFG: OnceCell<TokenCfg> = OnceCell::new();
+
+/// Issuer, key identifier and private key of the generated tokens.
+struct TokenCfg {
+	iss: String,
+	kid: String,
+	private_key: Vec<u8>,
+}
+
+/// Minimum size of the `aud` obfuscator key, unless overridden by the
+/// MASTER_ASAP_KEY_MIN_BITS environment variable.
+const MIN_AUD_KEY_BITS: usize = 256;
+
 /// A thread-safe cell which can be written to only once
-pub fn init_thread_safe_key() {
-	std::thread::spawn(|| {
-		let _value: String = MASTER_ASAP_KEY.get_or_init(|| {
-			let file_path = std::env::var("MASTER_ASAP_KEY")
-				.unwrap_or("./warden.key".to_owned());
+///
+/// Reads the `aud` obfuscator key from the file named by MASTER_ASAP_KEY
+/// (./warden.key by default).  Call it at startup, before any token is
+/// generated or validated.  Fails if the key file can't be read, isn't
+/// UTF-8, or holds a key shorter than the minimum size, which would silently
+/// weaken the AES encryption.  An unparseable MASTER_ASAP_KEY_MIN_BITS fails
+/// too, rather than falling back to the default.
+pub fn init_thread_safe_key() -> Result<(), Error> {
+	std::thread::spawn(|| -> Result<(), Error> {
+		if MASTER_ASAP_KEY.get().is_some() {
+			return Ok(());
+		}
+
+		let file_path = std::env::var("MASTER_ASAP_KEY")
+			.unwrap_or("./warden.key".to_owned());
+
+		log::debug!("Using `aud` obfuscator file {}", file_path);
+
+		let aud_key: Vec<u8> = std::fs::read(&file_path)?;
+		let min_bits = min_key_bits(std::env::var("MASTER_ASAP_KEY_MIN_BITS").ok())?;
+
+		log::debug!( "Using `aud` signer key of {} bits", aud_key.len() * 8);
+		check_key_size(&aud_key, min_bits)?;
+
+		let key = String::from_utf8(aud_key)
+			.map_err(|_| ErrorKind::Message("MASTER_ASAP_KEY file is not valid UTF-8"))?;
+		let _ = MASTER_ASAP_KEY.set(key);
+
+		Ok(())
+	}).join().ok().expect("Could not join a thread")
+}
+
+/// The minimum `aud` key size set by MASTER_ASAP_KEY_MIN_BITS, or
+/// `MIN_AUD_KEY_BITS` when unset.
+fn min_key_bits(value: Option<String>) -> Result<usize, Error> {
+	match value {
+		Some(v) => v.parse().map_err(|_| ErrorKind::Msg(format!(
+					"Invalid MASTER_ASAP_KEY_MIN_BITS {}, expected a number", v)).into()),
+		None => Ok(MIN_AUD_KEY_BITS),
+	}
+}
+
+/// Fails with `WeakKey` if `key` is shorter than `min_bits`.
+fn check_key_size(key: &[u8], min_bits: usize) -> Result<(), Error> {
+	let bits = key.len() * 8;
+	if bits < min_bits {
+		return Err(ErrorKind::WeakKey { bits, min_bits }.into());
+	}
+	Ok(())
+}
+
+/// Loads the token generator settings, falling back to `ISS` and `KID`, and
+/// reads the DER private key from TOKEN_PRIVATE_KEY_PATH.
+///
+/// Call it at startup: it fails if the key file is missing or isn't a DER
+/// encoded key, rather than on the first token generated.
+pub fn init_token_cfg() -> Result<(), Error> {
+	token_cfg().map(|_| ())
+}
+
+fn token_cfg() -> Result<&'static TokenCfg, Error> {
+	TOKEN_CFG.get_or_try_init(|| {
+		let var = |name: &str, default: &str| {
+			std::env::var(name).unwrap_or_else(|_| default.to_owned())
+		};
+		let cfg = TokenCfg {
+			iss: var("TOKEN_ISSUER", ISS),
+			kid: var("TOKEN_KID", KID),
+			private_key: load_private_key()?,
+		};
+
+		log::debug!("Generating tokens as issuer {} with kid {}", cfg.iss, cfg.kid);
+		Ok(cfg)
+	})
+}
 
-			log::debug!("Using `aud` obfuscator file {}", file_path);
+/// Reads the private key from TOKEN_PRIVATE_KEY_PATH, or falls back to the
+/// embedded development key in debug builds.
+fn load_private_key() -> Result<Vec<u8>, Error> {
+	let path = match std::env::var("TOKEN_PRIVATE_KEY_PATH") {
+		Ok(path) => path,
+		#[cfg(debug_assertions)]
+		Err(_) => {
+			log::warn!("TOKEN_PRIVATE_KEY_PATH is unset, using the development key");
+			return Ok(PKEY.to_vec());
+		},
+		#[cfg(not(debug_assertions))]
+		Err(_) => return Err(ErrorKind::Message("TOKEN_PRIVATE_KEY_PATH is not set").into()),
+	};
 
-			let aud_key: Vec<u8> = std::fs::read(&file_path).unwrap();
+	log::debug!("Using token private key file {}", path);
 
-			log::debug!( "Using `aud` signer key of {} bits", aud_key.len() * 8);
+	let key = std::fs::read(&path).map_err(|e| {
+		Error::from(ErrorKind::Msg(format!("Unable to read private key {}: {}", path, e)))
+	})?;
 
-			std::str::from_utf8(aud_key.as_slice()).unwrap().to_string()
+	// A DER encoded key is an ASN.1 SEQUENCE; anything else, e.g. a PEM file,
+	// would only fail when signing the first token.
+	if key.first() != Some(&0x30) {
+		return Err(ErrorKind::Msg(format!("Private key {} is not DER encoded", path)).into());
+	}
 
-		}).to_owned();
-	}).join().ok().expect("Could not join a thread");
+	Ok(key)
 }
 
 /// TokenType enumerates the type of Token: [Normal or Refresh]
@@ -131,19 +239,20 @@ impl <'a> TryFrom<&'a str> for TokenType {
 /// get_validator() is a constructor method for ValidatorBuilder.
 /// Incoming ASAP tokens must include resource server audience identifier in
 /// their `aud` claim in order for a token to be valid.
-pub fn get_validator(keyserver_uri: &str) -> ValidatorBuilder {
-	let audience_identifier = encrypt_aud_to_base64(AUD);
+pub fn get_validator(keyserver_uri: &str) -> Result<ValidatorBuilder, Error> {
+	let audience_identifier = encrypt_aud_to_base64(AUD)?;
 	let resource_server_audience = String::from(audience_identifier);
-	Validator::builder(String::from(keyserver_uri), resource_server_audience)
+	Ok(Validator::builder(String::from(keyserver_uri), resource_server_audience))
 }
 
 /// Generator builder for ASAP Claims.
-fn generator_build() -> Generator {
-	Generator::new(
-		ISS.to_string(),
-		KID.to_string(),
-		PKEY.to_vec(),
-		)
+fn generator_build() -> Result<Generator, Error> {
+	let cfg = token_cfg()?;
+	Ok(Generator::new(
+		cfg.iss.clone(),
+		cfg.kid.clone(),
+		cfg.private_key.clone(),
+		))
 }
 
 /// generate_token() takes in client_data which will be used in the 'aud'
@@ -154,13 +263,13 @@ fn generator_build() -> Generator {
 pub fn generate_token(token_type: TokenType, client_data: &str)
 	-> Result<String, Error>
 {
-	let mut generator = generator_build();
+	let mut generator = generator_build()?;
 	match token_type {
 		TokenType::Normal => {
 			let _ = generator.set_max_lifespan(NORMAL_LIFESPAN);
 			let normal_token = generator
 				.token(
-					default_aud(client_data),
+					default_aud(client_data)?,
 					set_token_type(TokenType::Normal)
 				)?;
 			Ok(normal_token)
@@ -169,7 +278,7 @@ pub fn generate_token(token_type: TokenType, client_data: &str)
 			let _ = generator.set_max_lifespan(REFRESH_LIFESPAN);
 			let refresh_token = generator
 				.token(
-					default_aud(client_data),
+					default_aud(client_data)?,
 					set_token_type(TokenType::Refresh)
 				)?;
 			Ok(refresh_token)
@@ -177,28 +286,40 @@ pub fn generate_token(token_type: TokenType, client_data: &str)
 	}
 }
 
-/// Encrypts the client_data to AES 256-bit, encoded as base64.
-pub fn encrypt_aud_to_base64(client_data: &str) -> String {
-	let key: Option<&String> = MASTER_ASAP_KEY.get();
-	let mut secret: MagicCrypt = new_magic_crypt!(key.unwrap().as_str(), 256);
+/// The `aud` obfuscator key, set by `init_thread_safe_key`.
+fn master_asap_key() -> Result<&'static str, Error> {
+	MASTER_ASAP_KEY
+		.get()
+		.map(String::as_str)
+		.ok_or_else(|| ErrorKind::Message("MASTER_ASAP_KEY is not initialized").into())
+}
+
+/// Encrypts the client_data to AES 256-bit, encoded as base64.  Fails if
+/// `init_thread_safe_key` hasn't loaded the key.
+pub fn encrypt_aud_to_base64(client_data: &str) -> Result<String, Error> {
+	let key = master_asap_key()?;
+	let mut secret: MagicCrypt = new_magic_crypt!(key, 256);
 	let aud_claims = secret.encrypt_str_to_base64(client_data);
 	log::info!("encrpted aud claims field: {}", aud_claims);
 
-	aud_claims.to_string()
+	Ok(aud_claims.to_string())
 }
 
 /// decrypt_aud() takes in AES 256-bit base64 encoded string and decrypts it.
+/// Fails if `init_thread_safe_key` hasn't loaded the key, or if the
+/// identifier wasn't encrypted with it.
 pub fn decrypt_aud(audience_identifier: &str) -> Result<String, Error> {
-	let key: Option<&String> = MASTER_ASAP_KEY.get();
-	let mut secret: MagicCrypt = new_magic_crypt!(key.unwrap().as_str(), 256);
-	let raw =  secret.decrypt_base64_to_string(audience_identifier).unwrap();
+	let key = master_asap_key()?;
+	let mut secret: MagicCrypt = new_magic_crypt!(key, 256);
+	let raw = secret.decrypt_base64_to_string(audience_identifier).map_err(|e| {
+		Error::from(ErrorKind::Msg(format!("Unable to decrypt aud claim: {:?}", e)))
+	})?;
 	Ok(raw)
 }
 
 /// Converts client_data to audience server identifier for generator consumption
-fn default_aud(client_data: &str) -> Aud {
-	let audience_identifier = Aud::One(encrypt_aud_to_base64(client_data));
-	audience_identifier
+fn default_aud(client_data: &str) -> Result<Aud, Error> {
+	Ok(Aud::One(encrypt_aud_to_base64(client_data)?))
 }
 
 /// type_of_token() is a helper method to include ExtraClaims hashMap of TokenType.
@@ -213,18 +334,32 @@ fn set_token_type(token_type: TokenType) -> Option<ExtraClaims> {
 	let mut extra_claims = HashMap::new();
 	match token_type {
 		TokenType::Normal => {
-			extra_claims.insert("TokenType".to_string(), json!(
+			extra_claims.insert(TOKEN_TYPE_CLAIM.to_string(), json!(
 					Into::<&str>::into(normal)));
 		},
 
 		TokenType::Refresh => {
-			extra_claims.insert("TokenType".to_string(), json!(
+			extra_claims.insert(TOKEN_TYPE_CLAIM.to_string(), json!(
 					Into::<&str>::into(refresh)));
 		},
 	}
 	Some(extra_claims)
 }
 
+/// token_type_from_claims() reads the TokenType ExtraClaim of a validated
+/// token, so callers can tell a Normal token from a Refresh token.  A missing
+/// or unknown TokenType claim is a Malformed token.
+pub fn token_type_from_claims(extra_claims: &ExtraClaims) -> Result<TokenType, Error> {
+	let claim = extra_claims
+		.get(TOKEN_TYPE_CLAIM)
+		.and_then(|value| value.as_str())
+		.ok_or_else(|| ErrorKind::Malformed("missing TokenType claim".to_owned()))?;
+
+	TokenType::try_from(claim).map_err(|_| {
+		Error::from(ErrorKind::Malformed(format!("unknown TokenType claim: {}", claim)))
+	})
+}
+
 /// aud_from_json() extracts the inner member of Aud enum variant.
 /// Its purpose is to get the `aud` variant from a token payload.
 ///
@@ -241,3 +376,53 @@ pub fn aud_from_json(data: &asap::claims::Aud) -> IoResult<String> {
 			).map(|r| r.clone())
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn token_type_claim_roundtrip() {
+		let claims = set_token_type(TokenType::Refresh).unwrap();
+		match token_type_from_claims(&claims) {
+			Ok(TokenType::Refresh) => (),
+			other => panic!("expected Refresh, got {:?}", other),
+		}
+	}
+
+	#[test]
+	fn unknown_token_type_claim_is_malformed() {
+		let mut claims = HashMap::new();
+		claims.insert(TOKEN_TYPE_CLAIM.to_string(), json!("Forever"));
+		match token_type_from_claims(&claims).map_err(|e| e.kind().to_string()) {
+			Err(msg) => assert_eq!(msg, "Malformed token: unknown TokenType claim: Forever"),
+			Ok(t) => panic!("expected Malformed, got {:?}", t),
+		}
+
+		assert!(token_type_from_claims(&HashMap::new()).is_err());
+	}
+
+	#[test]
+	fn short_aud_key_is_rejected() {
+		match check_key_size(&[7u8; 16], MIN_AUD_KEY_BITS).map_err(|e| e.kind().to_string()) {
+			Err(msg) => assert_eq!(msg, "Key of 128 bits is too short, expected at least 256 bits"),
+			Ok(()) => panic!("expected a WeakKey error"),
+		}
+		assert!(check_key_size(&[7u8; 32], MIN_AUD_KEY_BITS).is_ok());
+	}
+
+	#[test]
+	fn invalid_min_key_bits_is_an_error() {
+		assert_eq!(min_key_bits(None).unwrap(), MIN_AUD_KEY_BITS);
+		assert_eq!(min_key_bits(Some("128".to_owned())).unwrap(), 128);
+		assert!(min_key_bits(Some("lots".to_owned())).is_err());
+	}
+
+	#[test]
+	fn aud_without_key_is_an_error() {
+		if MASTER_ASAP_KEY.get().is_none() {
+			assert!(encrypt_aud_to_base64(AUD).is_err());
+			assert!(decrypt_aud("bm90LWVuY3J5cHRlZA==").is_err());
+		}
+	}
+}
@@ -0,0 +1,94 @@
+//! In-process event bus
+//!
+//! Controllers publish an `Event` after a successful commit; side effects
+//! such as notifications, audit logging or websocket feeds subscribe to the
+//! bus instead of being called from the handlers.
+use std::sync::{Arc, Mutex};
+
+use futures::sync::mpsc::{self, Receiver, Sender};
+use serde::Serialize;
+use uuid::Uuid;
+use warp::{filters::BoxedFilter, Filter};
+
+/// Number of events a subscriber may lag behind before it is dropped.
+const SUBSCRIBER_BUFFER: usize = 64;
+
+/// An event published by the controllers.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Event {
+    UserCreated { uuid: Uuid },
+    UserUpdated { uuid: Uuid },
+    UserDeactivated { uuid: Uuid },
+}
+
+/// A sharable handle to the event bus.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<Mutex<Vec<Sender<Event>>>>,
+}
+
+impl EventBus {
+    /// Subscribe to all events published from now on.  A subscriber lagging
+    /// more than `SUBSCRIBER_BUFFER` events behind is dropped, which ends
+    /// its stream.
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (tx, rx) = mpsc::channel(SUBSCRIBER_BUFFER);
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Publish an event to every subscriber, dropping the ones that have
+    /// gone away or can't keep up.
+    pub fn publish(&self, event: Event) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let mut kept = Vec::with_capacity(subscribers.len());
+
+        for mut subscriber in subscribers.drain(..) {
+            match subscriber.try_send(event.clone()) {
+                Ok(()) => kept.push(subscriber),
+                Err(ref e) if e.is_full() => {
+                    log::warn!("dropping event subscriber lagging behind");
+                }
+                Err(_) => (),
+            }
+        }
+        *subscribers = kept;
+    }
+
+    /// Create a filter extracting a handle to the event bus.
+    pub fn filter(&self) -> BoxedFilter<(EventBus,)> {
+        let bus = self.clone();
+        warp::any().map(move || bus.clone()).boxed()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::Stream;
+
+    #[test]
+    fn published_event_is_received() {
+        let bus = EventBus::default();
+        let rx = bus.subscribe();
+        let event = Event::UserCreated { uuid: Uuid::nil() };
+
+        bus.publish(event.clone());
+        drop(bus);
+
+        let received: Vec<Event> = rx.wait().map(Result::unwrap).collect();
+        assert_eq!(received, vec![event]);
+    }
+
+    #[test]
+    fn lagging_subscriber_is_dropped() {
+        let bus = EventBus::default();
+        let _rx = bus.subscribe();
+
+        for _ in 0..SUBSCRIBER_BUFFER + 2 {
+            bus.publish(Event::UserUpdated { uuid: Uuid::nil() });
+        }
+        assert!(bus.subscribers.lock().unwrap().is_empty());
+    }
+}
@@ -1,8 +1,18 @@
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error as StdError;
 use serde::{Deserialize, Serialize};
+use validator::{ValidationError, ValidationErrors, ValidationErrorsKind};
+
+use crate::config::CONF;
+use crate::i18n::Lang;
 
 pub const INTERNAL_SERVER_ERROR: Fault = Fault::Static(StaticException::InternalServerError);
 pub const NOT_FOUND: Fault = Fault::Static(StaticException::NotFound);
+pub const BAD_REQUEST: Fault = Fault::Static(StaticException::BadRequest);
+pub const METHOD_NOT_ALLOWED: Fault = Fault::Static(StaticException::MethodNotAllowed);
+pub const LENGTH_REQUIRED: Fault = Fault::Static(StaticException::LengthRequired);
+pub const PAYLOAD_TOO_LARGE: Fault = Fault::Static(StaticException::PayloadTooLarge);
+pub const UNSUPPORTED_MEDIA_TYPE: Fault = Fault::Static(StaticException::UnsupportedMediaType);
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
@@ -13,7 +23,42 @@ pub enum Fault {
     /// RateLimit handles exceptions related to filter gated request behind a
     /// leaky bucket rate limiter
     #[serde(rename = "/report/rate-limit")]
-    RateLimit(RateLimitException)
+    RateLimit(RateLimitException),
+    /// InvalidParams handles exceptions related to request parameters that
+    /// failed to parse or validate
+    #[serde(rename = "/report/invalid-params")]
+    InvalidParams(InvalidParams),
+    /// UnsupportedApiVersion handles requests asking for an API version
+    /// outside of the range a route supports
+    #[serde(rename = "/report/unsupported-api-version")]
+    UnsupportedApiVersion(UnsupportedApiVersionException),
+    /// MethodNotAllowed handles requests to a known path using a method the
+    /// route doesn't support
+    #[serde(rename = "/report/method-not-allowed")]
+    MethodNotAllowed(MethodNotAllowedException),
+    /// Unauthorized handles requests lacking valid authentication
+    #[serde(rename = "/report/unauthorized")]
+    Unauthorized(UnauthorizedException),
+    /// UriTooLong handles requests whose query string exceeds the limit
+    #[serde(rename = "/report/uri-too-long")]
+    UriTooLong(UriTooLongException),
+    /// BodyNotAllowed handles requests carrying a body on routes that don't
+    /// accept one
+    #[serde(rename = "/report/body-not-allowed")]
+    BodyNotAllowed,
+    /// GatewayTimeout handles handlers that didn't complete in time, e.g.
+    /// while waiting on an external service
+    #[serde(rename = "/report/gateway-timeout")]
+    GatewayTimeout,
+    /// RequestTimeout handles requests whose body wasn't received in time
+    #[serde(rename = "/report/request-timeout")]
+    RequestTimeout,
+    /// ServiceUnavailable handles write requests while in maintenance mode
+    #[serde(rename = "/report/service-unavailable")]
+    ServiceUnavailable(ServiceUnavailableException),
+    /// InvalidJson handles request bodies that are not the expected JSON
+    #[serde(rename = "/report/invalid-json")]
+    InvalidJson(InvalidJsonException),
 }
 
 impl Fault {
@@ -23,10 +68,30 @@ impl Fault {
 
         match self {
             Static(StaticException::NotFound) => StatusCode::NOT_FOUND,
+            Static(StaticException::BadRequest) => StatusCode::BAD_REQUEST,
+            Static(StaticException::MethodNotAllowed) => StatusCode::METHOD_NOT_ALLOWED,
+            Static(StaticException::LengthRequired) => StatusCode::LENGTH_REQUIRED,
+            Static(StaticException::PayloadTooLarge) => StatusCode::PAYLOAD_TOO_LARGE,
+            Static(StaticException::UnsupportedMediaType) => {
+                StatusCode::UNSUPPORTED_MEDIA_TYPE
+            },
             Static(StaticException::InternalServerError) => {
                 StatusCode::INTERNAL_SERVER_ERROR
             },
             RateLimit(_) =>  warp::http::StatusCode::TOO_MANY_REQUESTS,
+            InvalidParams(_) if CONF.server.invalid_params_status == 422 => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            },
+            InvalidParams(_) => StatusCode::BAD_REQUEST,
+            UnsupportedApiVersion(_) => StatusCode::BAD_REQUEST,
+            MethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            UriTooLong(_) => StatusCode::URI_TOO_LONG,
+            BodyNotAllowed => StatusCode::BAD_REQUEST,
+            GatewayTimeout => StatusCode::GATEWAY_TIMEOUT,
+            RequestTimeout => StatusCode::REQUEST_TIMEOUT,
+            ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            InvalidJson(_) => StatusCode::BAD_REQUEST,
         }
     }
 }
@@ -51,6 +116,21 @@ pub enum StaticException {
     #[serde(rename = "Not Found")]
     NotFound,
 
+    #[serde(rename = "Bad Request")]
+    BadRequest,
+
+    #[serde(rename = "Method Not Allowed")]
+    MethodNotAllowed,
+
+    #[serde(rename = "Length Required")]
+    LengthRequired,
+
+    #[serde(rename = "Payload Too Large")]
+    PayloadTooLarge,
+
+    #[serde(rename = "Unsupported Media Type")]
+    UnsupportedMediaType,
+
     #[serde(rename = "Internal Server Error")]
     InternalServerError,
 }
@@ -72,9 +152,19 @@ pub struct ExceptionMsg<'a> {
 
 impl<'a> From<&'a Fault> for ExceptionMsg<'a> {
     fn from(fault: &'a Fault) -> ExceptionMsg<'a> {
+        ExceptionMsg::localized(fault, Lang::En)
+    }
+}
+
+impl<'a> ExceptionMsg<'a> {
+    /// Create the problem document of a fault, with its title and detail in
+    /// the given language.  Static titles are the HTTP reason phrases and
+    /// aren't translated.
+    pub fn localized(fault: &'a Fault, lang: Lang) -> ExceptionMsg<'a> {
         use Fault::*;
 
         let status = Some(fault.to_status_code().as_u16());
+        let msgs = lang.catalog();
 
         let (title, detail) = match fault {
             Static(_) => {
@@ -82,11 +172,60 @@ impl<'a> From<&'a Fault> for ExceptionMsg<'a> {
             }
 
             RateLimit(_) => {
+                (Some(msgs.rate_limited.to_owned()), None)
+            }
+
+            InvalidParams(_) => {
+                (Some(msgs.invalid_params.to_owned()), None)
+            }
+
+            UnsupportedApiVersion(e) => {
                 (
-                    Some("Your request has been rate limited.".to_owned()),
-                    None,
+                    Some(msgs.unsupported_api_version.to_owned()),
+                    Some(msgs.supported_api_versions
+                         .replace("{min}", &e.min.to_string())
+                         .replace("{max}", &e.max.to_string())),
                     )
             }
+
+            MethodNotAllowed(_) => {
+                (Some(msgs.method_not_allowed.to_owned()), None)
+            }
+
+            Unauthorized(e) => {
+                let detail = match e.category {
+                    UnauthorizedCategory::Missing => msgs.bearer_token_missing,
+                    UnauthorizedCategory::Malformed => msgs.bearer_token_malformed,
+                };
+                (Some(msgs.unauthorized.to_owned()), Some(detail.to_owned()))
+            }
+
+            UriTooLong(e) => {
+                (
+                    Some(msgs.uri_too_long.to_owned()),
+                    Some(msgs.max_query_len.replace("{max_len}", &e.max_len.to_string())),
+                    )
+            }
+
+            BodyNotAllowed => {
+                (Some(msgs.body_not_allowed.to_owned()), None)
+            }
+
+            GatewayTimeout => {
+                (Some(msgs.gateway_timeout.to_owned()), None)
+            }
+
+            RequestTimeout => {
+                (Some(msgs.request_timeout.to_owned()), None)
+            }
+
+            ServiceUnavailable(_) => {
+                (Some(msgs.service_unavailable.to_owned()), None)
+            }
+
+            InvalidJson(e) => {
+                (Some(msgs.invalid_json.to_owned()), e.detail.clone())
+            }
         };
 
         ExceptionMsg {
@@ -96,6 +235,68 @@ impl<'a> From<&'a Fault> for ExceptionMsg<'a> {
             detail,
         }
     }
+
+    /// Renders the error as a JSON:API document, `{"errors": [...]}`.  Each
+    /// invalid param reason becomes its own error, pointing at the param
+    /// with `source.pointer`.
+    pub fn to_jsonapi(&self) -> serde_json::Value {
+        use serde_json::{json, Value};
+
+        let status = self.status.map(|status| status.to_string());
+        let title = match (&self.title, self.fault) {
+            (Some(title), _) => Some(title.clone()),
+            (None, Fault::Static(e)) => serde_json::to_value(e)
+                .ok()
+                .and_then(|e| e["title"].as_str().map(str::to_owned)),
+            (None, _) => None,
+        };
+        let error = |detail: Option<&String>, code: Option<&Value>, pointer: Option<String>| {
+            let mut error = json!({ "status": status, "title": title });
+            if let Some(detail) = detail {
+                error["detail"] = json!(detail);
+            }
+            if let Some(code) = code {
+                error["code"] = code.clone();
+            }
+            if let Some(pointer) = pointer {
+                error["source"] = json!({ "pointer": pointer });
+            }
+            error
+        };
+
+        let errors: Vec<Value> = match self.fault {
+            Fault::InvalidParams(params) => params
+                .invalid_params
+                .iter()
+                .flat_map(|(param, reasons)| reasons.iter().map(move |reason| (param, reason)))
+                .map(|(param, reason)| {
+                    let reason = serde_json::to_value(reason).unwrap_or(Value::Null);
+                    error(
+                        reason["message"].as_str().map(str::to_owned).as_ref(),
+                        Some(&reason["type"]),
+                        Some(json_pointer(param)),
+                        )
+                })
+                .collect(),
+            _ => vec![error(self.detail.as_ref(), None, None)],
+        };
+
+        match self.fault {
+            Fault::InvalidParams(params) if params.truncated => {
+                json!({ "errors": errors, "meta": { "truncated": true } })
+            }
+            _ => json!({ "errors": errors }),
+        }
+    }
+}
+
+/// Converts a param name like `items[2].email` to the JSON pointer
+/// `/items/2/email`, escaping `~` and `/` in names.
+fn json_pointer(param: &str) -> String {
+    param
+        .split(|c| c == '.' || c == '[')
+        .map(|part| part.trim_end_matches(']').replace('~', "~0").replace('/', "~1"))
+        .fold(String::new(), |pointer, part| pointer + "/" + &part)
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -103,3 +304,411 @@ impl<'a> From<&'a Fault> for ExceptionMsg<'a> {
 pub struct RateLimitException {
     pub wait_time_millis: u64,
 }
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnsupportedApiVersionException {
+    pub requested: String,
+    pub min: u32,
+    pub max: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MethodNotAllowedException {
+    pub allow: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnauthorizedException {
+    pub category: UnauthorizedCategory,
+}
+
+/// Why a request is not authenticated.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum UnauthorizedCategory {
+    /// No credentials were provided.
+    Missing,
+    /// Credentials were provided but could not be parsed.
+    Malformed,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UriTooLongException {
+    pub max_len: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceUnavailableException {
+    pub retry_after_secs: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InvalidJsonException {
+    pub category: InvalidJsonCategory,
+    #[serde(skip)]
+    pub detail: Option<String>,
+}
+
+/// Why a request body could not be deserialized.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum InvalidJsonCategory {
+    /// The body is not syntactically valid JSON.
+    Syntactic,
+    /// The body is valid JSON, but doesn't match the expected structure.
+    Semantic,
+    /// The body ended before the JSON value was complete, or is empty.
+    PrematureEnd,
+}
+
+impl InvalidJsonException {
+    pub fn new(category: InvalidJsonCategory, detail: impl Into<String>) -> Self {
+        InvalidJsonException { category, detail: Some(detail.into()) }
+    }
+}
+
+impl From<serde_json::Error> for InvalidJsonException {
+    fn from(error: serde_json::Error) -> Self {
+        use serde_json::error::Category;
+
+        let category = match error.classify() {
+            Category::Data => InvalidJsonCategory::Semantic,
+            Category::Eof => InvalidJsonCategory::PrematureEnd,
+            Category::Syntax | Category::Io => InvalidJsonCategory::Syntactic,
+        };
+
+        InvalidJsonException::new(category, error.to_string())
+    }
+}
+
+/// The request parameters that are invalid, keyed by parameter name.  Kept
+/// sorted so identical problems always serialize to identical bytes.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InvalidParams {
+    pub invalid_params: BTreeMap<String, Vec<InvalidParamsReason>>,
+    /// Whether reasons were left out, past the `max-invalid-params` limit.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+}
+
+impl InvalidParams {
+    /// Create an `InvalidParams` with a single reason for the given parameter.
+    pub fn single(param: &str, reason: InvalidParamsReason) -> Self {
+        let mut invalid_params = InvalidParams::default();
+        invalid_params.add(param, reason);
+        invalid_params
+    }
+
+    /// Add a reason why the given parameter is invalid.
+    pub fn add(&mut self, param: &str, reason: InvalidParamsReason) {
+        self.invalid_params
+            .entry(param.to_owned())
+            .or_insert_with(Vec::new)
+            .push(reason);
+    }
+
+    /// Requires at least one of the given optional fields, each paired with
+    /// whether it is present, e.g. for search or partial update bodies.
+    /// When all are absent, every field gets an `AtLeastOneRequired` reason
+    /// listing them all.
+    pub fn at_least_one(fields: &[(&str, bool)]) -> Result<(), InvalidParams> {
+        if fields.iter().any(|(_, present)| *present) {
+            return Ok(());
+        }
+
+        let names: Vec<String> = fields.iter().map(|(name, _)| (*name).to_owned()).collect();
+        let mut invalid_params = InvalidParams::default();
+        for name in &names {
+            invalid_params.add(name, InvalidParamsReason::AtLeastOneRequired {
+                fields: names.clone(),
+            });
+        }
+        Err(invalid_params)
+    }
+
+    /// Renders the reasons as human readable messages per param, e.g. for
+    /// displaying next to form fields.
+    pub fn to_field_messages(&self) -> HashMap<String, Vec<String>> {
+        self.invalid_params
+            .iter()
+            .map(|(param, reasons)| {
+                (param.clone(), reasons.iter().map(ToString::to_string).collect())
+            })
+            .collect()
+    }
+
+    /// Collects the reasons of validation errors, recursing into nested
+    /// structs (`parent.field`) and lists (`items[2].field`).  At most `max`
+    /// reasons are kept, in field name order, and the rest flagged as
+    /// `truncated`, so a body with many invalid fields can't amplify into a
+    /// huge problem document.
+    pub fn from_validation_errors(errors: &ValidationErrors, max: usize) -> Self {
+        let mut invalid_params = InvalidParams::default();
+        invalid_params.add_validation_errors("", errors, max);
+        invalid_params
+    }
+
+    fn reason_count(&self) -> usize {
+        self.invalid_params.values().map(Vec::len).sum()
+    }
+
+    fn add_validation_errors(&mut self, prefix: &str, errors: &ValidationErrors, max: usize) {
+        // Fields in name order, so the reasons kept past `max` are the same
+        // on every run.
+        let mut fields: Vec<_> = errors.errors().iter().collect();
+        fields.sort_by_key(|(field, _)| *field);
+
+        for (field, kind) in fields {
+            let param = if prefix.is_empty() {
+                field.to_string()
+            } else {
+                format!("{}.{}", prefix, field)
+            };
+
+            match kind {
+                ValidationErrorsKind::Field(errors) => {
+                    for error in errors {
+                        if self.reason_count() >= max {
+                            self.truncated = true;
+                            return;
+                        }
+                        self.add(&param, error.into());
+                    }
+                }
+                ValidationErrorsKind::Struct(errors) => {
+                    self.add_validation_errors(&param, errors, max);
+                }
+                ValidationErrorsKind::List(errors) => {
+                    for (index, errors) in errors {
+                        self.add_validation_errors(
+                            &format!("{}[{}]", param, index), errors, max);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl From<ValidationErrors> for InvalidParams {
+    fn from(errors: ValidationErrors) -> Self {
+        InvalidParams::from_validation_errors(&errors, CONF.server.max_invalid_params)
+    }
+}
+
+/// The reason a single request parameter is invalid.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum InvalidParamsReason {
+    /// The parameter must be a UUID.
+    MustBeUuid,
+    /// The parameter must be an email address.
+    MustBeEmailAddress,
+    /// The parameter must be a valid username.
+    MustBeUsername,
+    /// The parameter must be an international phone number.
+    MustBeValidPhone,
+    /// The parameter must be an integer of zero or more.
+    MustBeNonNegativeInteger,
+    /// The parameter names a field that doesn't exist.
+    UnknownField { field: String },
+    /// At least one of the fields must be given.
+    AtLeastOneRequired { fields: Vec<String> },
+    /// Any other reason, with the validator message when there is one.
+    Other {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+    },
+}
+
+impl std::fmt::Display for InvalidParamsReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use InvalidParamsReason::*;
+
+        match self {
+            MustBeUuid => f.write_str("must be a UUID"),
+            MustBeEmailAddress => f.write_str("must be an email address"),
+            MustBeUsername => f.write_str(
+                "must be 3 to 32 letters, digits, '_', '-' or '.'"),
+            MustBeValidPhone => f.write_str("must be an international phone number"),
+            MustBeNonNegativeInteger => f.write_str("must be an integer of zero or more"),
+            UnknownField { field } => write!(f, "unknown field '{}'", field),
+            AtLeastOneRequired { fields } => {
+                write!(f, "at least one of {} is required", fields.join(", "))
+            }
+            Other { message: Some(message) } => f.write_str(message),
+            Other { message: None } => f.write_str("is invalid"),
+        }
+    }
+}
+
+impl<'a> From<&'a ValidationError> for InvalidParamsReason {
+    fn from(validation_error: &'a ValidationError) -> Self {
+        match validation_error.code.as_ref() {
+            "email" => InvalidParamsReason::MustBeEmailAddress,
+            "phone" => InvalidParamsReason::MustBeValidPhone,
+            _ => InvalidParamsReason::Other {
+                message: validation_error.message.as_ref().map(|m| m.to_string()),
+            },
+        }
+    }
+}
+
+/// A malformed UUID, e.g. of a path segment or query param.  The cause is
+/// dropped, clients only need to know the value must be a UUID.
+impl From<uuid::parser::ParseError> for InvalidParamsReason {
+    fn from(_: uuid::parser::ParseError) -> Self {
+        InvalidParamsReason::MustBeUuid
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn email_error(field: &'static str) -> ValidationErrors {
+        let mut errors = ValidationErrors::new();
+        errors.add(field, ValidationError::new("email"));
+        errors
+    }
+
+    #[test]
+    fn uuid_parse_error_is_must_be_uuid() {
+        let error = uuid::Uuid::parse_str("not-a-uuid").unwrap_err();
+        let invalid_params = InvalidParams::single("id", error.into());
+        assert_eq!(
+            serde_json::to_value(&invalid_params).unwrap(),
+            serde_json::json!({"invalidParams": {"id": [{"type": "mustBeUuid"}]}})
+            );
+    }
+
+    #[test]
+    fn invalid_params_serialize_in_stable_order() {
+        let mut first = InvalidParams::default();
+        first.add("userName", InvalidParamsReason::MustBeUsername);
+        first.add("email", InvalidParamsReason::MustBeEmailAddress);
+
+        let mut second = InvalidParams::default();
+        second.add("email", InvalidParamsReason::MustBeEmailAddress);
+        second.add("userName", InvalidParamsReason::MustBeUsername);
+
+        assert_eq!(
+            serde_json::to_vec(&first).unwrap(),
+            serde_json::to_vec(&second).unwrap()
+            );
+    }
+
+    #[test]
+    fn invalid_params_from_nested_validation_errors() {
+        let items = ValidationErrors::merge_all(
+            Ok(()),
+            "items",
+            vec![Ok(()), Ok(()), Err(email_error("email"))],
+            );
+        let errors = ValidationErrors::merge(items, "owner", Err(email_error("email")))
+            .unwrap_err();
+
+        let invalid_params = InvalidParams::from(errors).invalid_params;
+        let params: Vec<&String> = invalid_params.keys().collect();
+        assert_eq!(params, vec!["items[2].email", "owner.email"]);
+
+        match invalid_params["items[2].email"].as_slice() {
+            [InvalidParamsReason::MustBeEmailAddress] => (),
+            other => panic!("unexpected reasons: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn problem_title_is_localized() {
+        let fault = Fault::UriTooLong(UriTooLongException { max_len: 2048 });
+        let msg = ExceptionMsg::localized(&fault, Lang::negotiate(vec!["fr", "es-mx"]));
+        assert_eq!(
+            msg.title.as_ref().unwrap(),
+            "La cadena de consulta de la solicitud es demasiado larga."
+            );
+        assert_eq!(
+            msg.detail.as_ref().unwrap(),
+            "La cadena de consulta puede tener como máximo 2048 bytes."
+            );
+
+        let msg = ExceptionMsg::localized(&fault, Lang::negotiate(vec!["fr"]));
+        assert_eq!(msg.title.as_ref().unwrap(), "The request query string is too long.");
+    }
+
+    #[test]
+    fn invalid_params_are_capped() {
+        let mut errors = ValidationErrors::new();
+        for field in &["a", "b", "c", "d", "e", "f", "g", "h"] {
+            errors.add(field, ValidationError::new("email"));
+        }
+
+        let invalid_params = InvalidParams::from_validation_errors(&errors, 3);
+        assert_eq!(invalid_params.reason_count(), 3);
+        let kept: Vec<&String> = invalid_params.invalid_params.keys().collect();
+        assert_eq!(kept, vec!["a", "b", "c"]);
+        assert!(invalid_params.truncated);
+        assert_eq!(serde_json::to_value(&invalid_params).unwrap()["truncated"], true);
+
+        let invalid_params = InvalidParams::from_validation_errors(&errors, 8);
+        assert_eq!(invalid_params.reason_count(), 8);
+        assert!(!invalid_params.truncated);
+        assert!(serde_json::to_value(&invalid_params).unwrap().get("truncated").is_none());
+    }
+
+    #[test]
+    fn invalid_params_as_jsonapi_errors() {
+        let mut params =
+            InvalidParams::single("items[2].email", InvalidParamsReason::MustBeEmailAddress);
+        params.add("userName", InvalidParamsReason::MustBeUsername);
+        let fault = Fault::InvalidParams(params);
+
+        let document = ExceptionMsg::from(&fault).to_jsonapi();
+        let errors = document["errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0]["source"]["pointer"], "/items/2/email");
+        assert_eq!(errors[0]["code"], "mustBeEmailAddress");
+        assert_eq!(errors[1]["source"]["pointer"], "/userName");
+        assert_eq!(errors[1]["title"], "Your request parameters did not validate.");
+    }
+
+    #[test]
+    fn invalid_params_to_field_messages() {
+        let mut params = InvalidParams::single("email", InvalidParamsReason::MustBeEmailAddress);
+        params.add("fields", InvalidParamsReason::UnknownField { field: "password".to_owned() });
+        params.add("password", InvalidParamsReason::Other { message: None });
+
+        let messages = params.to_field_messages();
+        assert_eq!(messages["email"], vec!["must be an email address"]);
+        assert_eq!(messages["fields"], vec!["unknown field 'password'"]);
+        assert_eq!(messages["password"], vec!["is invalid"]);
+    }
+
+    #[test]
+    fn at_least_one_field_is_required() {
+        assert!(InvalidParams::at_least_one(&[("email", false), ("userName", true)]).is_ok());
+
+        let invalid_params = InvalidParams::at_least_one(&[("email", false), ("userName", false)])
+            .unwrap_err();
+        let messages = invalid_params.to_field_messages();
+        assert_eq!(messages["email"], vec!["at least one of email, userName is required"]);
+        assert_eq!(messages["userName"], messages["email"]);
+    }
+
+    #[test]
+    fn other_reason_keeps_validator_message() {
+        let mut error = ValidationError::new("password_policy");
+        error.message = Some("must contain a digit".into());
+
+        let reason = serde_json::to_value(InvalidParamsReason::from(&error)).unwrap();
+        assert_eq!(reason, serde_json::json!({
+            "type": "other",
+            "message": "must contain a digit",
+        }));
+    }
+}
@@ -0,0 +1,99 @@
+//! Message catalogs
+//!
+//! Titles and details of problem documents, per supported language.  Details
+//! taking values use `{name}` placeholders.  English is the default.
+
+/// A language with a message catalog.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Lang {
+    En,
+    Es,
+}
+
+impl Lang {
+    /// The language of a lowercase language tag, matched on its primary
+    /// subtag (`es-mx` is `Es`).  Returns `None` for unsupported languages.
+    pub fn from_tag(tag: &str) -> Option<Lang> {
+        match tag.split('-').next() {
+            Some("en") => Some(Lang::En),
+            Some("es") => Some(Lang::Es),
+            _ => None,
+        }
+    }
+
+    /// The first supported language among tags in order of preference,
+    /// falling back to English.
+    pub fn negotiate<'a, I: IntoIterator<Item = &'a str>>(tags: I) -> Lang {
+        tags.into_iter().find_map(Lang::from_tag).unwrap_or(Lang::En)
+    }
+
+    pub fn catalog(self) -> &'static Catalog {
+        match self {
+            Lang::En => &EN,
+            Lang::Es => &ES,
+        }
+    }
+}
+
+impl Default for Lang {
+    fn default() -> Self {
+        Lang::En
+    }
+}
+
+/// Problem document titles and details in one language.
+pub struct Catalog {
+    pub rate_limited: &'static str,
+    pub invalid_params: &'static str,
+    pub unsupported_api_version: &'static str,
+    /// Placeholders: `{min}`, `{max}`.
+    pub supported_api_versions: &'static str,
+    pub method_not_allowed: &'static str,
+    pub unauthorized: &'static str,
+    pub bearer_token_missing: &'static str,
+    pub bearer_token_malformed: &'static str,
+    pub uri_too_long: &'static str,
+    /// Placeholder: `{max_len}`.
+    pub max_query_len: &'static str,
+    pub body_not_allowed: &'static str,
+    pub gateway_timeout: &'static str,
+    pub request_timeout: &'static str,
+    pub service_unavailable: &'static str,
+    pub invalid_json: &'static str,
+}
+
+const EN: Catalog = Catalog {
+    rate_limited: "Your request has been rate limited.",
+    invalid_params: "Your request parameters did not validate.",
+    unsupported_api_version: "The requested API version is not supported.",
+    supported_api_versions: "Supported versions are {min} through {max}.",
+    method_not_allowed: "The request method is not allowed on this resource.",
+    unauthorized: "Your request is not authenticated.",
+    bearer_token_missing: "No bearer token was provided.",
+    bearer_token_malformed: "The bearer token is malformed.",
+    uri_too_long: "The request query string is too long.",
+    max_query_len: "The query string may be at most {max_len} bytes.",
+    body_not_allowed: "This request must not have a body.",
+    gateway_timeout: "Your request took too long to complete.",
+    request_timeout: "Your request body took too long to arrive.",
+    service_unavailable: "The service is in maintenance and read-only.",
+    invalid_json: "Your request body is not valid JSON.",
+};
+
+const ES: Catalog = Catalog {
+    rate_limited: "Su solicitud ha sido limitada.",
+    invalid_params: "Los parámetros de su solicitud no son válidos.",
+    unsupported_api_version: "La versión de la API solicitada no es compatible.",
+    supported_api_versions: "Las versiones compatibles son de la {min} a la {max}.",
+    method_not_allowed: "El método de la solicitud no está permitido en este recurso.",
+    unauthorized: "Su solicitud no está autenticada.",
+    bearer_token_missing: "No se proporcionó un token de portador.",
+    bearer_token_malformed: "El token de portador está mal formado.",
+    uri_too_long: "La cadena de consulta de la solicitud es demasiado larga.",
+    max_query_len: "La cadena de consulta puede tener como máximo {max_len} bytes.",
+    body_not_allowed: "Esta solicitud no debe tener cuerpo.",
+    gateway_timeout: "Su solicitud tardó demasiado en completarse.",
+    request_timeout: "El cuerpo de su solicitud tardó demasiado en llegar.",
+    service_unavailable: "El servicio está en mantenimiento y en solo lectura.",
+    invalid_json: "El cuerpo de su solicitud no es JSON válido.",
+};
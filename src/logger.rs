@@ -0,0 +1,124 @@
+//! Log mechanism
+//!
+//! Wraps the femme logger with per-module level directives, using the
+//! `RUST_LOG` syntax: a comma separated list of `level` or `target=level`
+//! entries, e.g. `info,sentry::token=debug`.  A bare level sets the default
+//! for every target; the longest matching target prefix wins.
+use femme::pretty::Logger;
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+/// A logger filtering records per target before handing them to femme.
+struct TargetLogger {
+    inner: Logger,
+    default: LevelFilter,
+    directives: Vec<(String, LevelFilter)>,
+}
+
+impl TargetLogger {
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.directives
+            .iter()
+            .filter(|(module, _)| {
+                target == module || target.starts_with(&format!("{}::", module))
+            })
+            .max_by_key(|(module, _)| module.len())
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+}
+
+impl Log for TargetLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// `RUST_LOG`-style directives: a default level and per-target levels.
+pub struct Directives {
+    default: LevelFilter,
+    targets: Vec<(String, LevelFilter)>,
+    /// The entries that couldn't be parsed, and are ignored.  Log them once
+    /// the logger is started.
+    pub invalid: Vec<String>,
+}
+
+impl Directives {
+    /// Parses `RUST_LOG`-style directives.  Unparseable entries are skipped
+    /// and collected in `invalid`.
+    pub fn parse(spec: &str) -> Self {
+        let mut default = LevelFilter::Info;
+        let mut targets = Vec::new();
+        let mut invalid = Vec::new();
+
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let mut parts = directive.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(level), None) => match level.parse() {
+                    Ok(level) => default = level,
+                    Err(_) => invalid.push(directive.to_owned()),
+                },
+                (Some(target), Some(level)) => match level.parse() {
+                    Ok(level) => targets.push((target.to_owned(), level)),
+                    Err(_) => invalid.push(directive.to_owned()),
+                },
+                _ => (),
+            }
+        }
+
+        Directives { default, targets, invalid }
+    }
+}
+
+/// Installs the femme logger, filtered by the given directives.  Fails if a
+/// logger is already installed, leaving that one in place.
+pub fn start(directives: Directives) -> Result<(), SetLoggerError> {
+    let Directives { default, targets, .. } = directives;
+    let max_level = targets
+        .iter()
+        .map(|(_, level)| *level)
+        .fold(default, std::cmp::max);
+
+    log::set_boxed_logger(Box::new(TargetLogger {
+        inner: Logger::new(),
+        default,
+        directives: targets,
+    }))?;
+    log::set_max_level(max_level);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn longest_target_prefix_wins() {
+        let Directives { default, targets, .. } =
+            Directives::parse("warn, sentry=info,sentry::token=debug");
+        let logger = TargetLogger { inner: Logger::new(), default, directives: targets };
+
+        assert_eq!(logger.level_for("oxide::api"), LevelFilter::Warn);
+        assert_eq!(logger.level_for("sentry::hash"), LevelFilter::Info);
+        assert_eq!(logger.level_for("sentry::token"), LevelFilter::Debug);
+        assert_eq!(logger.level_for("sentry_other"), LevelFilter::Warn);
+    }
+
+    #[test]
+    fn invalid_directives_are_returned() {
+        let directives = Directives::parse("debug,oxide=loud,verbose");
+        assert_eq!(directives.default, LevelFilter::Debug);
+        assert!(directives.targets.is_empty());
+        assert_eq!(directives.invalid, vec!["oxide=loud", "verbose"]);
+    }
+}
@@ -1,73 +1,189 @@
 #[macro_use]
 #[cfg(feature = "sentry")] pub mod sentry;
 #[macro_use] extern crate failure;
+#[macro_use] extern crate diesel_migrations;
 
 mod config;
 mod error;
+mod events;
 mod exception;
+mod i18n;
+mod logger;
 mod payload;
 mod rate_limit;
+mod shutdown;
+mod storage;
+mod timestamp;
+mod types;
 mod utils;
 
 use std::env;
+use std::time::{Duration, Instant};
 
 use chrono::Utc;
 use dotenv::dotenv;
-use femme::pretty::Logger;
+use futures::{sync::oneshot, Future};
 use log;
 use terminator::Terminator;
-use warp::{Filter, path};
+use tokio::{runtime::Runtime, timer::Delay};
+use warp::{http::Method, Filter, path};
+
+use crate::config::CONF;
 
 fn main() -> Result<(), Terminator> {
     dotenv().ok();
-    Logger::new().start(log::LevelFilter::Info)?;
-    log::info!("log mechanism initialized...");
+    // An already installed logger (e.g. by a test harness) keeps logging.
+    let directives = logger::Directives::parse(&CONF.log.directives);
+    let invalid_directives = directives.invalid.clone();
+    match logger::start(directives) {
+        Ok(()) => log::info!("log mechanism initialized..."),
+        Err(e) => log::warn!("keeping the logger already installed: {}", e),
+    }
+    for directive in invalid_directives {
+        log::warn!("ignoring invalid log directive: {}", directive);
+    }
+    config::log_effective(&CONF);
 
     let db_pool = utils::pg_pool();
+    if CONF.database.run_migrations_on_start {
+        storage::run_migrations(&db_pool)?;
+    }
+    let health_pool = db_pool.clone();
     let _db = utils::pg(db_pool);
 
     let rate_limiter = rate_limit::leaky_bucket();
+    let in_flight = shutdown::InFlight::default();
+
+    let cors = warp::cors()
+        .allow_any_origin()
+        .allow_methods(vec!["GET", "POST", "PUT", "DELETE"])
+        .allow_headers(vec!["Accept", "Authorization", "Content-Type"])
+        .expose_headers(CONF.server.cors_expose_headers.iter().map(String::as_str))
+        .max_age(CONF.server.cors_max_age_secs);
 
-    let bundle_oxide = rate_limiter
+    let api = warp::any()
+        .map(Instant::now)
+        .and(rate_limiter)
+        .and(utils::maintenance())
+        .and(utils::max_query_len(CONF.server.max_query_len))
+        .and(in_flight.track())
         .and(
-            warp::path!("version")
-            .map(|| payload::ResponseBuilder::ok()
-                 .body(env!("CARGO_PKG_VERSION")
-                 )
+            utils::trailing_slash()
+            .or(warp::path!("version")
+                .and(utils::named("version.get", utils::methods(&[Method::GET], utils::no_body()
+                    .and(utils::request_id())
+                    .and_then(|id| utils::handler(id, || payload::ResponseBuilder::ok()
+                         .body(payload::BuildInfo::current())
+                    ))
+                )))
+            )
+            .unify()
+            .or(path!("health")
+                .and(utils::named("health.get", utils::methods(&[Method::GET], utils::no_body()
+                    .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+                    .and(utils::request_id())
+                    .and_then(move |query: String, id: Option<String>| {
+                        let health_pool = health_pool.clone();
+                        utils::handler(id, move || {
+                            // Pool metrics are opt-in, keeping the basic probe cheap.
+                            let verbose = query.split('&').any(|p| p == "verbose=true");
+                            let pool = if verbose { Some(&health_pool) } else { None };
+                            payload::ResponseBuilder::ok()
+                                .body(payload::Health::new(pool))
+                        })
+                    })
+                )))
             )
+            .unify()
             .or(path!("time")
-                .map(|| payload::ResponseBuilder::ok()
-                     .body(Utc::now().to_rfc3339())
-                )
+                .and(utils::named("time.get", utils::methods(&[Method::GET], utils::no_body()
+                    .and(utils::request_id())
+                    .and_then(|id| utils::handler(id, || payload::ResponseBuilder::ok()
+                         .body(Utc::now().to_rfc3339())
+                    ))
+                )))
             )
             .unify(),
             )
         .and(warp::header("Accept"))
-        .map(|resp: payload::Response, _accept: String| {
-            let mut http_resp_builder = warp::http::response::Builder::new();
-            http_resp_builder.status(resp.status_code());
-            http_resp_builder.header("Content-Type", "application/json");
-
-            for (header, value) in resp.headers() {
-                http_resp_builder.header(header.as_bytes(), value.clone());
-            }
-
-            match resp.value() {
-                Some(value) => http_resp_builder
-                    .body(serde_json::to_string(value).unwrap())
-                    .unwrap(),
-                None => http_resp_builder.body("".to_owned()).unwrap()
-            }
-        })
-    .recover(utils::handle_rejection)
+        .and(utils::request_id())
+        .and(warp::filters::method::method())
+        .and_then(|started: Instant,
+              _in_flight: shutdown::InFlightGuard,
+              resp: payload::Response,
+              _accept: String,
+              request_id: Option<String>,
+              method: Method| {
+            utils::catch_panic(request_id.as_ref().map(String::as_str), || {
+                let dur = started.elapsed().as_secs_f64() * 1000.0;
+                log::info!(
+                    target: "oxide::access",
+                    "{} {} {} {:.3}ms",
+                    method,
+                    resp.route().unwrap_or("-"),
+                    resp.status_code().as_u16(),
+                    dur
+                    );
+
+                let mut http_resp_builder = warp::http::response::Builder::new();
+                http_resp_builder.status(resp.status_code());
+                if !resp.headers().contains_key("Content-Type") {
+                    http_resp_builder.header("Content-Type", payload::APPLICATION_JSON);
+                }
+                if CONF.server.server_timing {
+                    http_resp_builder.header("Server-Timing", format!("total;dur={:.3}", dur));
+                }
+
+                for (header, value) in resp.headers() {
+                    http_resp_builder.header(header.as_bytes(), value.clone());
+                }
+
+                let body = resp.to_json(CONF.server.wrap_responses);
+                http_resp_builder.body(body.unwrap_or_default()).unwrap()
+            })
+        });
+
+    let bundle_oxide = utils::localize_rejections(utils::base_path().and(api))
+        .or(utils::spa_fallback())
+        .recover(utils::handle_rejection)
+        .with(cors)
+        .with(warp::reply::with::headers(utils::security_headers()))
+        .with(warp::reply::with::headers(utils::server_headers()))
         .with(warp::log("oxide::api"));
 
 
-    warp::serve(bundle_oxide)
-        .run(
+    let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+    let (addr, server) = warp::serve(bundle_oxide)
+        .bind_with_graceful_shutdown(
             // localhost
-            ([127, 0, 0, 1], 8080)
+            ([127, 0, 0, 1], 8080),
+            shutdown_rx.map_err(|_| ()),
         );
+    log::info!("listening on {}", addr);
+
+    // Once signalled, stop accepting connections and give in-flight
+    // requests the grace period to finish.
+    let grace = Duration::from_secs(CONF.server.shutdown_grace_secs);
+    let drained = in_flight.clone();
+    let server = server.map(move |_| {
+        log::info!("all in-flight requests drained");
+    });
+    let drain_timeout = shutdown::signal()
+        .and_then(move |_| {
+            log::info!("shutdown signal received, draining for up to {:?}", grace);
+            let _ = shutdown_tx.send(());
+            Delay::new(Instant::now() + grace).map_err(|_| ())
+        })
+        .map(move |_| {
+            log::warn!(
+                "grace period elapsed with {} requests in flight, forcing close",
+                drained.count()
+            );
+        });
+
+    let mut runtime = Runtime::new()?;
+    let _ = runtime.block_on(server.select(drain_timeout));
+    runtime.shutdown_now().wait().ok();
 
     Ok(())
 }
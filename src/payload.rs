@@ -1,22 +1,154 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 use erased_serde::Serialize as ErasedSerialize;
+use serde::Serialize;
 use warp::http::StatusCode;
 
+use crate::config::{ErrorFormat, CONF};
+use crate::exception::{ExceptionMsg, Fault};
+
+/// Content type of JSON response bodies.
+pub const APPLICATION_JSON: &str = "application/json";
+/// Content type of RFC 7807 problem documents.
+pub const PROBLEM_JSON: &str = "application/problem+json";
+/// Content type of JSON:API documents.
+pub const JSONAPI_JSON: &str = "application/vnd.api+json";
+
+/// The content type and body of an error document, in the configured
+/// `error_format`.
+pub fn error_document(msg: &ExceptionMsg) -> (&'static str, serde_json::Value) {
+    match CONF.server.error_format {
+        ErrorFormat::Problem => (
+            PROBLEM_JSON,
+            serde_json::to_value(msg).expect("Failed to serialize problem document"),
+            ),
+        ErrorFormat::Jsonapi => (JSONAPI_JSON, msg.to_jsonapi()),
+    }
+}
+
 pub struct Response {
     value: Option<Box<dyn ErasedSerialize + Send>>,
     status_code: StatusCode,
-    headers: HashMap<String, String>,
+    headers: BTreeMap<String, String>,
+    /// Whether the value is already a `{data, ...}` envelope.
+    enveloped: bool,
+    /// Name of the route that built the response, see `utils::named`.
+    route: Option<&'static str>,
+}
+
+/// A success response value wrapped with `wrap_responses`.
+#[derive(Serialize)]
+struct Envelope<'a> {
+    data: &'a (dyn ErasedSerialize + Send),
+}
+
+/// The RFC 7240 `return` preference of a request creating or updating a
+/// resource: whether the response carries the resource.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReturnPreference {
+    /// An empty body, the resource being at the `Location`.
+    Minimal,
+    /// The full resource in the body.
+    Representation,
+}
+
+impl ReturnPreference {
+    /// The preference as sent in `Prefer` and `Preference-Applied`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReturnPreference::Minimal => "return=minimal",
+            ReturnPreference::Representation => "return=representation",
+        }
+    }
+}
+
+impl Default for ReturnPreference {
+    fn default() -> Self {
+        ReturnPreference::Representation
+    }
 }
 
 pub struct ResponseBuilder {
     status_code: StatusCode,
-    headers: HashMap<String, String>,
+    headers: BTreeMap<String, String>,
+}
+
+/// Build metadata of the running service.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildInfo {
+    version: &'static str,
+    git_sha: &'static str,
+    build_timestamp: &'static str,
+    rustc_version: &'static str,
+}
+
+impl BuildInfo {
+    /// The build metadata set at compile time by the build script.
+    pub fn current() -> Self {
+        BuildInfo {
+            version: env!("CARGO_PKG_VERSION"),
+            git_sha: env!("GIT_SHA"),
+            build_timestamp: env!("BUILD_TIMESTAMP"),
+            rustc_version: env!("RUSTC_VERSION"),
+        }
+    }
+}
+
+/// Service health, with the database pool metrics when requested.
+#[derive(Serialize)]
+pub struct Health {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    db: Option<PoolHealth>,
+}
+
+/// Connection pool saturation metrics.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PoolHealth {
+    connections_in_use: u32,
+    idle: u32,
+    max: u32,
+}
+
+impl Health {
+    /// The service health, including the pool metrics if `pool` is given.
+    pub fn new(pool: Option<&crate::utils::PgPool>) -> Self {
+        let db = pool.map(|pool| {
+            let state = pool.state();
+            PoolHealth {
+                connections_in_use: state.connections - state.idle_connections,
+                idle: state.idle_connections,
+                max: pool.max_size(),
+            }
+        });
+
+        Health { status: "ok", db }
+    }
+}
+
+/// A page of items wrapped together with its pagination metadata.
+#[derive(Serialize)]
+pub struct Paginated<T> {
+    data: Vec<T>,
+    meta: PageMeta,
+}
+
+/// Pagination metadata of a `Paginated` list.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PageMeta {
+    total: u64,
+    limit: u64,
+    offset: u64,
+    /// The offset of the next page, or `None` on the last page.
+    next_offset: Option<u64>,
 }
 
 impl Response {
     /// The response headers.
-    pub fn headers(&self) -> &HashMap<String, String> {
+    pub fn headers(&self) -> &BTreeMap<String, String> {
         &self.headers
     }
 
@@ -25,10 +157,34 @@ impl Response {
         self.status_code
     }
 
+    /// The name of the route that built the response, if it has one.
+    pub fn route(&self) -> Option<&'static str> {
+        self.route
+    }
+
+    /// Tag the response with the name of the route that built it.
+    pub fn with_route(mut self, name: &'static str) -> Self {
+        self.route = Some(name);
+        self
+    }
+
     /// The response value.
     pub fn value(&self) -> &Option<Box<dyn ErasedSerialize + Send>> {
         &self.value
     }
+
+    /// The serialized response body, if any.  With `wrap`, the value of a
+    /// success response not already enveloped goes under a `data` key.
+    pub fn to_json(&self, wrap: bool) -> Option<String> {
+        let value = self.value.as_ref()?;
+        let json = if wrap && self.status_code.is_success() && !self.enveloped {
+            serde_json::to_string(&Envelope { data: value.as_ref() })
+        } else {
+            serde_json::to_string(value)
+        };
+
+        Some(json.expect("Failed to serialize response body"))
+    }
 }
 
 impl ResponseBuilder {
@@ -36,7 +192,24 @@ impl ResponseBuilder {
     pub fn new(status_code: StatusCode) -> Self {
         ResponseBuilder {
             status_code,
-            headers: HashMap::new(),
+            headers: BTreeMap::new(),
+        }
+    }
+
+    /// Set a header on the payload response.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.insert(name.to_owned(), value.to_owned());
+        self
+    }
+
+    /// Build the payload response without a body.
+    pub fn empty(self) -> Response {
+        Response {
+            value: None,
+            status_code: self.status_code,
+            headers: self.headers,
+            enveloped: false,
+            route: None,
         }
     }
 
@@ -49,11 +222,293 @@ impl ResponseBuilder {
                 value: Some(Box::new(value) as Box<dyn ErasedSerialize + Send>),
                 status_code: self.status_code,
                 headers: self.headers,
+                enveloped: false,
+                route: None,
+            }
+        }
+
+    /// Build the payload response with a list of items wrapped in a
+    /// `{data, meta}` envelope carrying the pagination metadata.
+    pub fn paginated<T>(self, items: Vec<T>, total: u64, limit: u64, offset: u64) -> Response
+        where
+        T: Serialize + Send + 'static,
+        {
+            // The offset comes from the client, so the next one may overflow.
+            let meta = PageMeta {
+                total,
+                limit,
+                offset,
+                next_offset: offset.checked_add(limit).filter(|next| *next < total),
+            };
+
+            Response {
+                enveloped: true,
+                ..self.body(Paginated { data: items, meta })
+            }
+        }
+
+    /// Create an error document response for the given fault, for handlers
+    /// returning a structured error without rejecting.
+    pub fn problem(fault: &Fault) -> Response {
+        let (content_type, document) = error_document(&ExceptionMsg::from(fault));
+
+        Self::new(fault.to_status_code())
+            .header("Content-Type", content_type)
+            .body(document)
+    }
+
+    /// Create a 201 Created response for a resource at `location`, with the
+    /// resource in the body unless the client prefers a minimal return.  The
+    /// applied preference is echoed in `Preference-Applied`.
+    pub fn created<T>(location: &str, value: T, prefer: ReturnPreference) -> Response
+        where
+        T: ErasedSerialize + Send + 'static,
+        {
+            let builder = Self::new(StatusCode::CREATED)
+                .header("Location", location)
+                .header("Preference-Applied", prefer.as_str());
+
+            match prefer {
+                ReturnPreference::Minimal => builder.empty(),
+                ReturnPreference::Representation => builder.body(value),
             }
         }
 
+    /// Create a bodiless response with a 304 Not Modified status code.
+    pub fn not_modified() -> Response {
+        Self::new(StatusCode::NOT_MODIFIED).empty()
+    }
+
     /// Create a response with a 200 OK status code.
     pub fn ok() -> Self {
         Self::new(StatusCode::OK)
     }
 }
+
+/// Serializes `value` keeping only the requested top-level `fields`, for
+/// `?fields=id,userName` style partial responses.  Field names match the
+/// serialized camelCase keys; snake_case names are accepted as well.  A name
+/// that isn't a field of the serialized value is an invalid `fields` param,
+/// so fields a response type never serializes (e.g. `password`) can't be
+/// selected.
+pub fn select_fields<T>(value: &T, fields: &[&str]) -> Result<serde_json::Value, Fault>
+where
+    T: Serialize,
+{
+    use crate::exception::{InvalidParams, InvalidParamsReason};
+
+    fn camel_case(field: &str) -> String {
+        let mut parts = field.split('_');
+        let mut camel = parts.next().unwrap_or("").to_owned();
+        for part in parts {
+            let mut chars = part.chars();
+            if let Some(first) = chars.next() {
+                camel.extend(first.to_uppercase());
+                camel.push_str(chars.as_str());
+            }
+        }
+        camel
+    }
+
+    let mut object = match serde_json::to_value(value) {
+        Ok(serde_json::Value::Object(object)) => object,
+        _ => return Err(crate::exception::INTERNAL_SERVER_ERROR),
+    };
+
+    let mut selected = serde_json::Map::new();
+    let mut invalid = InvalidParams::default();
+    for field in fields.iter().map(|f| camel_case(f.trim())) {
+        match object.remove(&field) {
+            Some(v) => {
+                selected.insert(field, v);
+            }
+            None if selected.contains_key(&field) => (),
+            None => invalid.add("fields", InvalidParamsReason::UnknownField { field }),
+        }
+    }
+
+    if invalid.invalid_params.is_empty() {
+        Ok(serde_json::Value::Object(selected))
+    } else {
+        Err(Fault::InvalidParams(invalid))
+    }
+}
+
+/// Deserializes a field that distinguishes "absent" from "null", for partial
+/// updates.  Use on an `Option<Option<T>>` field together with
+/// `#[serde(default, deserialize_with = "crate::payload::nullable")]`:
+///
+/// * an omitted field is `None`: leave the stored value unchanged,
+/// * `null` is `Some(None)`: clear the stored value,
+/// * a value is `Some(Some(value))`: set the stored value.
+pub fn nullable<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: serde::Deserialize<'de>,
+    D: serde::Deserializer<'de>,
+{
+    serde::Deserialize::deserialize(deserializer).map(Some)
+}
+
+/// Route URIs, for `Location` headers and links, so they stay consistent
+/// with the mounted routes.
+pub mod uri {
+    use uuid::Uuid;
+
+    /// Route prefix of the user resources.
+    pub const USERS: &str = "users";
+
+    use crate::config::CONF;
+
+    /// The absolute path of the given segments, each percent-encoded, under
+    /// the configured `api_base_path`.
+    pub fn path<S: AsRef<str>>(segments: &[S]) -> String {
+        let base = CONF.server.api_base_path.clone();
+        segments.iter().fold(base, |mut path, segment| {
+            path.push('/');
+            for byte in segment.as_ref().bytes() {
+                match byte {
+                    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                        path.push(byte as char)
+                    }
+                    _ => path.push_str(&format!("%{:02X}", byte)),
+                }
+            }
+            path
+        })
+    }
+
+    /// The URI of a user resource.
+    pub fn user(uuid: &Uuid) -> String {
+        path(&[USERS, uuid.to_hyphenated().to_string().as_str()])
+    }
+
+    /// The URI of a page of a list resource at `path`.
+    pub fn page(path: &str, limit: u64, offset: u64) -> String {
+        format!("{}?limit={}&offset={}", path, limit, offset)
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[test]
+        fn segments_are_encoded() {
+            assert_eq!(path(&["users", "a b/c"]), "/users/a%20b%2Fc");
+            assert_eq!(path::<&str>(&[]), "");
+        }
+
+        #[test]
+        fn user_uri() {
+            let uuid = Uuid::parse_str("936DA01F9ABD4d9d80C702AF85C822A8").unwrap();
+            assert_eq!(user(&uuid), "/users/936da01f-9abd-4d9d-80c7-02af85c822a8");
+            assert_eq!(
+                page(&path(&[USERS]), 20, 40),
+                "/users?limit=20&offset=40"
+                );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Profile {
+        user_name: String,
+        email: String,
+    }
+
+    #[test]
+    fn bare_response_body() {
+        let resp = super::ResponseBuilder::ok().body(serde_json::json!({"id": 1}));
+        assert_eq!(resp.to_json(false).unwrap(), r#"{"id":1}"#);
+        assert!(super::ResponseBuilder::not_modified().to_json(false).is_none());
+    }
+
+    #[test]
+    fn wrapped_response_body() {
+        let resp = super::ResponseBuilder::ok().body(serde_json::json!({"id": 1}));
+        assert_eq!(resp.to_json(true).unwrap(), r#"{"data":{"id":1}}"#);
+
+        let page = super::ResponseBuilder::ok().paginated(vec![1], 1, 10, 0);
+        assert!(page.to_json(true).unwrap().starts_with(r#"{"data":[1],"meta":"#));
+
+        let problem = super::ResponseBuilder::problem(&crate::exception::Fault::BodyNotAllowed)
+            .to_json(true)
+            .unwrap();
+        assert!(!problem.starts_with(r#"{"data""#));
+    }
+
+    #[test]
+    fn paginated_last_offset_has_no_next() {
+        let page = super::ResponseBuilder::ok().paginated(vec![1], 30, 10, 10);
+        assert!(page.to_json(false).unwrap().contains(r#""nextOffset":20"#));
+
+        let page = super::ResponseBuilder::ok().paginated(Vec::<u8>::new(), 30, 10, u64::max_value());
+        assert!(page.to_json(false).unwrap().contains(r#""nextOffset":null"#));
+    }
+
+    #[test]
+    fn created_minimal_has_no_body() {
+        let resp = super::ResponseBuilder::created(
+            "/users/1",
+            serde_json::json!({"id": 1}),
+            super::ReturnPreference::Minimal,
+            );
+        assert_eq!(resp.status_code(), warp::http::StatusCode::CREATED);
+        assert_eq!(resp.headers()["Location"], "/users/1");
+        assert_eq!(resp.headers()["Preference-Applied"], "return=minimal");
+        assert!(resp.to_json(false).is_none());
+    }
+
+    #[test]
+    fn created_representation_has_body() {
+        let resp = super::ResponseBuilder::created(
+            "/users/1",
+            serde_json::json!({"id": 1}),
+            super::ReturnPreference::default(),
+            );
+        assert_eq!(resp.headers()["Preference-Applied"], "return=representation");
+        assert_eq!(resp.to_json(false).unwrap(), r#"{"id":1}"#);
+    }
+
+    #[test]
+    fn select_fields_keeps_requested_fields() {
+        let profile = Profile {
+            user_name: "ada".to_owned(),
+            email: "ada@example.com".to_owned(),
+        };
+
+        let selected = super::select_fields(&profile, &["user_name"]).unwrap();
+        assert_eq!(selected, serde_json::json!({"userName": "ada"}));
+
+        assert!(super::select_fields(&profile, &["email", "password"]).is_err());
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Update {
+        #[serde(default, deserialize_with = "super::nullable")]
+        display_name: Option<Option<String>>,
+    }
+
+    #[test]
+    fn nullable_omitted_is_unchanged() {
+        let update: Update = serde_json::from_str("{}").unwrap();
+        assert_eq!(update.display_name, None);
+    }
+
+    #[test]
+    fn nullable_null_is_cleared() {
+        let update: Update = serde_json::from_str(r#"{"displayName":null}"#).unwrap();
+        assert_eq!(update.display_name, Some(None));
+    }
+
+    #[test]
+    fn nullable_value_is_set() {
+        let update: Update = serde_json::from_str(r#"{"displayName":"Ada"}"#).unwrap();
+        assert_eq!(update.display_name, Some(Some("Ada".to_owned())));
+    }
+}
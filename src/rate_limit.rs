@@ -1,28 +1,38 @@
 use crate::exception::{Fault, RateLimitException};
+use crate::utils;
 use ratelimit_meter::{algorithms::NonConformance, KeyedRateLimiter};
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::{Arc, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use warp::{Filter, Rejection};
 
-/// Create a filter that gates a request behind a leaky bucket rate limiter.
+/// Requests a client may make per `PERIOD` before being rate limited.
+pub const CAPACITY: u32 = 2;
+/// The period over which the leaky bucket drains `CAPACITY` requests.
+pub const PERIOD: Duration = Duration::from_secs(1);
+
+/// Create a filter that gates a request behind a leaky bucket rate limiter,
+/// keyed by client address (see `utils::client_ip`).
 ///
 /// # Panics
 /// Panics if it is used with a transport not using socket addresses.
 pub fn leaky_bucket() -> impl Filter<Extract = (), Error = Rejection> + Clone {
-    let limiter = Arc::new(Mutex::new(KeyedRateLimiter::<SocketAddr>::new(
-                std::num::NonZeroU32::new(2u32).unwrap(),
-                std::time::Duration::from_secs(1),
+    let limiter = Arc::new(Mutex::new(KeyedRateLimiter::<IpAddr>::new(
+                std::num::NonZeroU32::new(CAPACITY).unwrap(),
+                PERIOD,
                 )));
 
     warp::addr::remote()
-        .and_then(move |addr: Option<SocketAddr>| {
+        .and(warp::header::optional::<String>("X-Forwarded-For"))
+        .and_then(move |addr: Option<SocketAddr>, forwarded: Option<String>| {
             let addr = addr
                 .expect(
                     "Must be used with a transport utilizing socket addresses."
                 );
+            let ip = utils::client_ip(Some(addr), forwarded.as_ref().map(String::as_str))
+                .unwrap_or_else(|| addr.ip());
             let mut limiter = limiter.lock().unwrap();
-            match limiter.check(addr) {
+            match limiter.check(ip) {
                 Ok(_) => Ok(()),
                 Err(neg) => Err(
                     warp::reject::custom(Fault::RateLimit(RateLimitException {
@@ -0,0 +1,52 @@
+//! Graceful shutdown
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use futures::{Future, Stream};
+use warp::{filters::BoxedFilter, Filter};
+
+/// Counts the requests currently being handled.
+#[derive(Clone, Default)]
+pub struct InFlight {
+    count: Arc<AtomicUsize>,
+}
+
+/// Marks a single request as in flight for as long as it is alive.
+pub struct InFlightGuard {
+    count: Arc<AtomicUsize>,
+}
+
+impl InFlight {
+    /// The number of requests currently in flight.
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// Create a filter extracting an `InFlightGuard`.  Keep the guard until
+    /// the response has been built.
+    pub fn track(&self) -> BoxedFilter<(InFlightGuard,)> {
+        let count = self.count.clone();
+
+        warp::any()
+            .map(move || {
+                count.fetch_add(1, Ordering::SeqCst);
+                InFlightGuard { count: count.clone() }
+            })
+            .boxed()
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.count.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A future resolving when the process receives a shutdown (ctrl-c) signal.
+pub fn signal() -> impl Future<Item = (), Error = ()> {
+    tokio_signal::ctrl_c()
+        .flatten_stream()
+        .into_future()
+        .map(|_| ())
+        .map_err(|_| ())
+}
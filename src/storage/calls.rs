@@ -0,0 +1,24 @@
+//! Reusable database calls, generic over the queried models.
+use diesel::connection::{Connection, SimpleConnection};
+use diesel::pg::PgConnection;
+use diesel::result::Error as DieselError;
+
+/// Runs `f` in a transaction with a local `statement_timeout` of `ms`
+/// milliseconds.  `SET LOCAL` only lasts until the transaction ends, so the
+/// connection's own timeout is back in place afterwards.
+///
+/// Use it for potentially large list and search queries, so a slow scan
+/// can't tie up a pooled connection indefinitely.
+pub fn with_statement_timeout<T, F>(
+    conn: &PgConnection,
+    ms: u64,
+    f: F,
+    ) -> Result<T, DieselError>
+where
+F: FnOnce(&PgConnection) -> Result<T, DieselError>,
+{
+    conn.transaction(|| {
+        conn.batch_execute(&format!("SET LOCAL statement_timeout = {}", ms))?;
+        f(conn)
+    })
+}
@@ -0,0 +1,21 @@
+//! Persistance storage
+pub mod calls;
+
+use crate::utils::PgPool;
+
+embed_migrations!();
+
+/// Runs the embedded migrations that haven't been applied to the database
+/// yet, logging each one applied.
+pub fn run_migrations(pool: &PgPool) -> Result<(), failure::Error> {
+    let conn = pool.get()?;
+    let mut output = Vec::new();
+    embedded_migrations::run_with_output(&conn, &mut output)?;
+
+    for line in String::from_utf8_lossy(&output).lines() {
+        log::info!("{}", line);
+    }
+    log::info!("database migrations are up to date");
+
+    Ok(())
+}
@@ -0,0 +1,120 @@
+//! Timestamp serialization helpers.
+//!
+//! Every timestamp on the wire is an RFC 3339 UTC string, the same format the
+//! `/time` endpoint returns.  Use on `NaiveDateTime` fields with
+//! `#[serde(with = "crate::timestamp::rfc3339")]`.
+use chrono::NaiveDateTime;
+
+use crate::utils::http_date;
+
+/// A model with `created_at`/`updated_at` columns, both UTC.
+pub trait Timestamped {
+    fn created_at(&self) -> NaiveDateTime;
+    fn updated_at(&self) -> NaiveDateTime;
+}
+
+/// The `Last-Modified` header value of a model.
+pub fn last_modified<T: Timestamped>(item: &T) -> String {
+    http_date(&item.updated_at())
+}
+
+/// A weak `ETag` header value of a model, changing with every update.  Built
+/// from the seconds and nanoseconds apart, as nanoseconds since the epoch
+/// overflow outside of roughly 1677 to 2262.
+pub fn etag<T: Timestamped>(item: &T) -> String {
+    let updated_at = item.updated_at();
+    format!(
+        "W/\"{}.{:09}\"",
+        updated_at.timestamp(),
+        updated_at.timestamp_subsec_nanos()
+        )
+}
+
+pub mod rfc3339 {
+    use chrono::{DateTime, NaiveDateTime, Utc};
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    /// Serializes a `NaiveDateTime`, assumed to be UTC, as an RFC 3339 string.
+    pub fn serialize<S>(dt: &NaiveDateTime, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let utc = DateTime::<Utc>::from_utc(*dt, Utc);
+        serializer.serialize_str(&utc.to_rfc3339())
+    }
+
+    /// Deserializes an RFC 3339 string into a UTC `NaiveDateTime`.
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<NaiveDateTime, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        DateTime::parse_from_rfc3339(&s)
+            .map(|dt| dt.naive_utc())
+            .map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::NaiveDate;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Stamped {
+        #[serde(with = "super::rfc3339")]
+        at: NaiveDateTime,
+    }
+
+    #[test]
+    fn rfc3339_roundtrip() {
+        let stamped = Stamped {
+            at: NaiveDate::from_ymd(2019, 10, 1).and_hms(12, 30, 5),
+        };
+        let json = serde_json::to_string(&stamped).unwrap();
+        assert_eq!(json, r#"{"at":"2019-10-01T12:30:05+00:00"}"#);
+
+        let parsed: Stamped = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, stamped);
+    }
+
+    #[test]
+    fn rfc3339_normalizes_offset_to_utc() {
+        let parsed: Stamped =
+            serde_json::from_str(r#"{"at":"2019-10-01T14:30:05+02:00"}"#).unwrap();
+        assert_eq!(parsed.at, NaiveDate::from_ymd(2019, 10, 1).and_hms(12, 30, 5));
+    }
+
+    impl Timestamped for Stamped {
+        fn created_at(&self) -> NaiveDateTime {
+            self.at
+        }
+
+        fn updated_at(&self) -> NaiveDateTime {
+            self.at
+        }
+    }
+
+    #[test]
+    fn cache_validators_follow_updated_at() {
+        let mut stamped = Stamped {
+            at: NaiveDate::from_ymd(2019, 10, 1).and_hms(12, 30, 5),
+        };
+        let before = etag(&stamped);
+        assert_eq!(last_modified(&stamped), "Tue, 01 Oct 2019 12:30:05 GMT");
+
+        stamped.at += chrono::Duration::milliseconds(1);
+        assert_ne!(etag(&stamped), before);
+        assert!(etag(&stamped).starts_with("W/\""));
+    }
+
+    #[test]
+    fn etag_of_distant_dates() {
+        let past = Stamped { at: NaiveDate::from_ymd(1600, 1, 1).and_hms(0, 0, 0) };
+        let future = Stamped { at: NaiveDate::from_ymd(2300, 1, 1).and_hms_nano(0, 0, 0, 5) };
+
+        assert_eq!(etag(&past), "W/\"-11676096000.000000000\"");
+        assert_eq!(etag(&future), "W/\"10413792000.000000005\"");
+    }
+}
@@ -0,0 +1,302 @@
+//! Validated value types
+//!
+//! Values are trimmed, normalized and validated on construction, so holding
+//! an `Email`, a `Username`, a `Phone` or a `Cidr` guarantees it is well
+//! formed.
+use std::convert::TryFrom;
+use std::fmt::{self, Display};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use serde::{de, Deserialize, Deserializer, Serialize};
+
+use crate::exception::InvalidParamsReason;
+
+/// Minimum and maximum length of a username, in characters.
+const USERNAME_LEN: (usize, usize) = (3, 32);
+
+/// A trimmed, lowercase email address.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(transparent)]
+pub struct Email(String);
+
+/// A trimmed, lowercase username of 3 to 32 alphanumeric, `_`, `-` or `.`
+/// characters.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(transparent)]
+pub struct Username(String);
+
+/// A phone number normalized to E.164, e.g. `+14155552671`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(transparent)]
+pub struct Phone(String);
+
+impl Phone {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Phone {
+    type Err = InvalidParamsReason;
+
+    /// Accepts an international number with an optional `00` or `+` prefix,
+    /// ignoring spaces, dots, dashes and parentheses used for grouping.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let digits = if s.starts_with('+') {
+            &s[1..]
+        } else if s.starts_with("00") {
+            &s[2..]
+        } else {
+            return Err(InvalidParamsReason::MustBeValidPhone);
+        };
+
+        let mut phone = String::from("+");
+        for c in digits.chars() {
+            match c {
+                '0'..='9' => phone.push(c),
+                ' ' | '.' | '-' | '(' | ')' => (),
+                _ => return Err(InvalidParamsReason::MustBeValidPhone),
+            }
+        }
+
+        // E.164: a country code not starting with 0, at most 15 digits.
+        let len = phone.len() - 1;
+        if len >= 8 && len <= 15 && !phone[1..].starts_with('0') {
+            Ok(Phone(phone))
+        } else {
+            Err(InvalidParamsReason::MustBeValidPhone)
+        }
+    }
+}
+
+impl TryFrom<String> for Phone {
+    type Error = InvalidParamsReason;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl<'de> Deserialize<'de> for Phone {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|_| de::Error::custom("must be an international phone number"))
+    }
+}
+
+impl Display for Phone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// An IPv4 or IPv6 network in CIDR notation, e.g. `10.0.0.0/8` or
+/// `fc00::/7`.  A bare address is a network of that single address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// Whether `ip` belongs to this network.  An IPv4 address never belongs
+    /// to an IPv6 network, and the other way around.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::max_value()
+                    .checked_shl(32 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(*ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::max_value()
+                    .checked_shl(128 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(*ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid CIDR `{}`", s);
+        let mut parts = s.trim().splitn(2, '/');
+        let addr: IpAddr = parts.next().unwrap_or("").parse().map_err(|_| invalid())?;
+        let max_len: u8 = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match parts.next() {
+            Some(len) => len.parse::<u8>().ok().filter(|len| *len <= max_len).ok_or_else(invalid)?,
+            None => max_len,
+        };
+
+        Ok(Cidr { addr, prefix_len })
+    }
+}
+
+impl<'de> Deserialize<'de> for Cidr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+impl Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+impl Email {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Username {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Email {
+    type Err = InvalidParamsReason;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let email = s.trim().to_lowercase();
+        if validator::validate_email(&email) {
+            Ok(Email(email))
+        } else {
+            Err(InvalidParamsReason::MustBeEmailAddress)
+        }
+    }
+}
+
+impl FromStr for Username {
+    type Err = InvalidParamsReason;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let username = s.trim().to_lowercase();
+        let len = username.chars().count();
+        let valid_chars = username
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-' || c == '.');
+
+        if len >= USERNAME_LEN.0 && len <= USERNAME_LEN.1 && valid_chars {
+            Ok(Username(username))
+        } else {
+            Err(InvalidParamsReason::MustBeUsername)
+        }
+    }
+}
+
+impl TryFrom<String> for Email {
+    type Error = InvalidParamsReason;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl TryFrom<String> for Username {
+    type Error = InvalidParamsReason;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl<'de> Deserialize<'de> for Email {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|_| de::Error::custom("must be an email address"))
+    }
+}
+
+impl<'de> Deserialize<'de> for Username {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|_| {
+            de::Error::custom(format!(
+                    "must be {} to {} letters, digits, '_', '-' or '.'",
+                    USERNAME_LEN.0, USERNAME_LEN.1
+                    ))
+        })
+    }
+}
+
+impl Display for Email {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Display for Username {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn email_is_trimmed_and_lowercased() {
+        let email: Email = "  Ada@Example.COM ".parse().unwrap();
+        assert_eq!(email.as_str(), "ada@example.com");
+        assert!("not-an-email".parse::<Email>().is_err());
+    }
+
+    #[test]
+    fn username_is_validated() {
+        let username: Username = " Ada_L ".parse().unwrap();
+        assert_eq!(username.as_str(), "ada_l");
+        assert!("ab".parse::<Username>().is_err());
+        assert!("ada lovelace".parse::<Username>().is_err());
+    }
+
+    #[test]
+    fn phone_is_normalized_to_e164() {
+        let phone: Phone = "+1 (415) 555-2671".parse().unwrap();
+        assert_eq!(phone.as_str(), "+14155552671");
+        let phone: Phone = "0044 20 7946 0958".parse().unwrap();
+        assert_eq!(phone.as_str(), "+442079460958");
+
+        assert!("415 555 2671".parse::<Phone>().is_err());
+        assert!("+1 415 CALL-NOW".parse::<Phone>().is_err());
+        assert!("+1234".parse::<Phone>().is_err());
+    }
+
+    #[test]
+    fn cidr_matches_v4_and_v6() {
+        let net: Cidr = "10.1.0.0/16".parse().unwrap();
+        assert!(net.contains(&"10.1.200.3".parse().unwrap()));
+        assert!(!net.contains(&"10.2.0.1".parse().unwrap()));
+        assert!(!net.contains(&"::ffff:10.1.0.1".parse().unwrap()));
+
+        let net: Cidr = "fc00::/7".parse().unwrap();
+        assert!(net.contains(&"fd12:3456::1".parse().unwrap()));
+        assert!(!net.contains(&"2001:db8::1".parse().unwrap()));
+
+        let any: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains(&"203.0.113.9".parse().unwrap()));
+        let single: Cidr = "127.0.0.1".parse().unwrap();
+        assert_eq!(single.to_string(), "127.0.0.1/32");
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("localhost/8".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn deserialize_rejects_invalid_values() {
+        let email: Email = serde_json::from_str(r#""ADA@example.com""#).unwrap();
+        assert_eq!(serde_json::to_string(&email).unwrap(), r#""ada@example.com""#);
+        assert!(serde_json::from_str::<Email>(r#""nope""#).is_err());
+    }
+}